@@ -1,32 +1,66 @@
-use crate::arrange::{arrange_grid, CellSize};
+use crate::arrange::{arrange_grid, CellSize, GRID_SIZE};
 use crate::card::Card;
 use crate::config::Config;
-use crate::data::{parse_data_from_file, save_data_to_file, Data};
+use crate::data::{read_source, save_data_to_file, Data};
+use crate::diff::match_cards;
+use crate::error::{LoadError, LoadErrors};
+use crate::field::key_on_line;
+use crate::merge::{apply_merge, merge_entries, MergeEntry};
+use crate::state::{remember_theme, remembered_theme};
+use crate::storage::{changed_on_disk, Fingerprint};
 
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEvent},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::prelude::*;
 use ratatui::widgets::{canvas::*, *};
-use tui_textarea::TextArea;
+use tui_textarea::{CursorMove, TextArea};
 
 use std::fs::OpenOptions;
 use std::io;
 use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 
 use crate::write_info;
 
 pub struct App {
     data: Data,
-    config: Config,
     tui_state: TuiState,
-    message: String,
     oops_count: usize,
     text_area: TextArea<'static>,
     source_file: String,
+    dirty: bool,
+    message: Option<(String, Color)>,
+    // The file as last read or written, to notice changes made by others
+    base: Data,
+    fingerprint: Option<Fingerprint>,
+    merge: Option<Merge>,
+    // Last change on disk already reported, so it is not reported again
+    reported: Option<Fingerprint>,
+    metadata_file: Option<String>,
+    metadata_fingerprint: Option<Fingerprint>,
+    repair: Option<Repair>,
+}
+
+// How often the content and metadata files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+// The file on disk and the cards that differ from the open document
+struct Merge {
+    disk: Data,
+    fingerprint: Option<Fingerprint>,
+    entries: Vec<MergeEntry>,
+}
+
+// A content file that does not parse, open as text until it is fixed
+struct Repair {
+    // What is wrong with the text as it is now, `None` once it parses
+    errors: Option<LoadErrors>,
+    trailing_newline: bool,
 }
 
 enum TuiState {
@@ -34,38 +68,94 @@ enum TuiState {
     Edit(usize),
     Create(usize, usize, usize),
     Delete(usize),
+    ConfirmQuit(usize),
+    Conflict(usize),
+    Merge(usize, usize),
+    Repair,
     Quit,
 }
 
 impl App {
-    pub fn new(filename: String) -> Result<Self> {
-        let items = if !filename.is_empty() {
-            parse_data_from_file(&filename)?
+    pub fn new(filename: String, config: Config, theme: Option<String>) -> Result<Self> {
+        if filename.is_empty() {
+            return Err(anyhow!("No content file given, run `obentou new <file>` to create one"));
+        }
+
+        // A missing file starts a fresh document, written out on save
+        let exists = Path::new(&filename).exists();
+        let fingerprint = Fingerprint::of_file(&filename);
+        let loaded = if exists {
+            Data::load(config.get_metadata().clone(), &filename)
         } else {
-            vec![]
+            Data::create(config.get_metadata().clone(), false)
         };
 
-        let tui_state = TuiState::Select(0);
-        let message = "ok".to_string();
-        let oops_count = 0;
-        let text_area = TextArea::new(items.get(0).context("Empty data")?.get_lines().to_vec());
+        // A file that does not parse is opened as text, to be fixed in place
+        let (mut data, repair) = match loaded {
+            Ok(data) => (data, None),
+            Err(e) => {
+                let errors = e.downcast::<LoadErrors>()?;
+                let data = Data::create(config.get_metadata().clone(), false)?;
+                (data, Some(errors))
+            }
+        };
 
-        let config = Config::load("metadata.toml")?;
-        let data = Data {
-            metadata: config.get_metadata().clone(),
-            items,
+        let oops_count = 0;
+        let (tui_state, text_area, repair) = match repair {
+            Some(errors) => {
+                let text = read_source(&filename)?;
+                let mut text_area = TextArea::new(text.lines().map(str::to_string).collect());
+                jump_to_error(&mut text_area, &errors);
+                let repair = Repair { errors: Some(errors), trailing_newline: text.ends_with('\n') };
+                (TuiState::Repair, text_area, Some(repair))
+            }
+            None => {
+                let text_area = TextArea::new(data.items.first().context("Empty data")?.get_lines().to_vec());
+                (TuiState::Select(0), text_area, None)
+            }
         };
 
+        // An explicit theme must exist, a remembered one may have been removed since
+        if let Some(theme) = theme {
+            data.metadata.set_theme(&theme)?;
+            if let Err(e) = remember_theme(&filename, &theme) {
+                write_info!(format!("Failed to remember theme: {}", e));
+            }
+        } else if let Some(theme) = remembered_theme(&filename) {
+            let _ = data.metadata.set_theme(&theme);
+        }
+
         write_info!("Initialize app...");
 
+        let message = if repair.is_some() {
+            Some((format!("{} does not parse, fix it to open its cards", filename), Color::Red))
+        } else if data.get_version() < data.metadata.get_version() {
+            Some((
+                format!("{} is at schema version {}, run `obentou migrate` to upgrade it", filename, data.get_version()),
+                Color::Yellow,
+            ))
+        } else {
+            None
+        };
+
+        let metadata_file = config.get_path().map(|path| path.to_string());
+        let metadata_fingerprint = metadata_file.as_deref().and_then(Fingerprint::of_file);
+
         Ok(Self {
-            data,
-            config,
             tui_state,
-            message,
             oops_count,
             text_area,
             source_file: filename,
+            dirty: !exists,
+            message,
+            base: data.clone(),
+            fingerprint,
+            merge: None,
+            reported: None,
+            metadata_file,
+            metadata_fingerprint,
+            repair,
+            data,
         })
     }
 
@@ -94,6 +184,14 @@ impl App {
 
         terminal.draw(|f| {
             let size = f.size();
+            // The columns end above the status bar, so their bottom borders stay visible
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Min(0),
+                    Constraint::Length(3),
+                ])
+                .split(size);
             // 创建三列布局
             let chunks = Layout::default()
                 .direction(Direction::Horizontal)
@@ -102,7 +200,7 @@ impl App {
                     Constraint::Percentage(50), // 第二列宽度
                     Constraint::Percentage(30), // 第三列宽度
                 ])
-                .split(size);
+                .split(rows[0]);
 
             // 第一列：标题列表
             let titles_list = self.data.items
@@ -110,7 +208,7 @@ impl App {
                 .enumerate()
                 .map(|(i, item)| {
                     
-                    let mut style = Style::default().fg(*self.data.metadata.get_card_color(&item.get_title()));
+                    let mut style = Style::default().fg(*self.data.metadata.get_card_color(item.get_title()));
 
                     if let TuiState::Select(selected_index) = self.tui_state {
                         if i == selected_index {
@@ -124,9 +222,7 @@ impl App {
                     } else {
                         format!("{}-{}", item.get_title(), item.get_shape())
                     };
-                    ListItem::new(Span::styled(String::from(
-                        format!("{} {}", prefix, title_and_shape)
-                    ), style))
+                    ListItem::new(Span::styled(format!("{} {}", prefix, title_and_shape), style))
                 })
                 .collect::<Vec<_>>();
 
@@ -141,11 +237,24 @@ impl App {
                 )).borders(Borders::ALL))
                 .highlight_style(Style::default().fg(Color::Yellow));
 
-            f.render_widget(titles_widget, chunks[0]);
+            // What keeps the file from parsing takes the place of the cards
+            if let (TuiState::Repair, Some(repair)) = (&self.tui_state, &self.repair) {
+                let problems = match &repair.errors {
+                    Some(errors) => Text::from(errors.0.iter().flat_map(problem_lines).collect::<Vec<_>>()),
+                    None => Text::styled("No problems left", Style::default().fg(Color::Green)),
+                };
+                let problems_widget = Paragraph::new(problems)
+                    .wrap(Wrap { trim: false })
+                    .block(Block::default().title(Span::styled("Problems", Style::default().fg(Color::Yellow))).borders(Borders::ALL));
+                f.render_widget(problems_widget, chunks[0]);
+            } else {
+                f.render_widget(titles_widget, chunks[0]);
+            }
 
             // 第二列：内容编辑器
             let mut editor_title = String::from("Edit");
             let mut editor_style = Style::default();
+            let mut field_help = None;
 
             if let TuiState::Edit(selected_index) = self.tui_state {
 
@@ -156,6 +265,12 @@ impl App {
                 let json_str = self.text_area.lines().join("\n");
                 let is_valid = self.data.metadata.is_valid(&json_str, title);
 
+                // Describe the field under the cursor
+                field_help = self.text_area.lines()
+                    .get(self.text_area.cursor().0)
+                    .and_then(|line| key_on_line(line))
+                    .and_then(|field| self.data.metadata.field_help(title, &field));
+
                 match is_valid {
                     Ok(()) => {
                         self.oops_count = 0;
@@ -170,14 +285,37 @@ impl App {
                 }
             }
 
-            self.text_area.set_block(
-                Block::default()
-                    .title(Span::styled(
-                        editor_title,
-                        editor_style,
-                    ))
-                    .borders(Borders::ALL),
-            );
+            if let (TuiState::Repair, Some(repair)) = (&self.tui_state, &self.repair) {
+                let ooo = "o".repeat(self.oops_count);
+                let mut cursor_line_style = Style::default();
+                match &repair.errors {
+                    Some(errors) => {
+                        editor_title = format!("Repair - O{ooo}ps! {} problem(s)", errors.0.len());
+                        editor_style = Style::default().fg(Color::Red);
+                        // Highlight the line the first error is on while the cursor is there
+                        if errors.first_position().is_some_and(|(line, _)| line == self.text_area.cursor().0 + 1) {
+                            cursor_line_style = Style::default().bg(Color::Red).fg(Color::White);
+                        }
+                    }
+                    None => {
+                        editor_title = String::from("Repair - OK");
+                        editor_style = Style::default().fg(Color::Green);
+                    }
+                }
+                self.text_area.set_cursor_line_style(cursor_line_style);
+            }
+
+            let mut editor_block = Block::default()
+                .title(Span::styled(
+                    editor_title,
+                    editor_style,
+                ))
+                .borders(Borders::ALL);
+            // Describe the field under the cursor on the bottom border
+            if let Some(help) = field_help {
+                editor_block = editor_block.title_bottom(Span::styled(help, Style::default().fg(Color::Cyan)));
+            }
+            self.text_area.set_block(editor_block);
 
             self.text_area.set_line_number_style(Style::default().fg(Color::DarkGray));
 
@@ -198,6 +336,9 @@ impl App {
                         TuiState::Select(idx) => idx,
                         TuiState::Create(idx, _, _) => idx,
                         TuiState::Delete(idx) => idx,
+                        TuiState::ConfirmQuit(idx) => idx,
+                        TuiState::Conflict(idx) => idx,
+                        TuiState::Merge(idx, _) => idx,
                         _ => 0,
                     };
 
@@ -214,24 +355,28 @@ impl App {
                                               .map(|item| format!("{}-{}", item.get_title(), item.get_shape()))
                                               .collect();
 
-                    let cell_size_list: Vec<CellSize> = arrange_grid((50, 8), &main_cards);
+                    let cell_size_list: Vec<CellSize> = arrange_grid(GRID_SIZE, &main_cards);
 
                     // write_info!(format!("cell size list: {:?}", cell_size_list));
 
-                    let (selected_y, selected_h) = if selected_index >= 1 && selected_index-1 < cell_size_list.len() {
-                        (start_y + (cell_size_list[selected_index-1].get_start_row() * 20) as f64 + gap_y,
-                         (cell_size_list[selected_index-1].get_height() * 20) as f64 - gap_y * 2.0)
+                    let selected_cell = cell_size_list
+                        .iter()
+                        .find(|c| c.get_card_index() + 1 == selected_index);
+
+                    let (selected_y, selected_h) = if let Some(cell) = selected_cell {
+                        (start_y + (cell.get_start_row() * 20) as f64 + gap_y,
+                         (cell.get_height() * 20) as f64 - gap_y * 2.0)
                     } else {
                         (0f64, 0f64)
                     };
 
-                    let offset_y = if (max_y as f64) < selected_y + selected_h {
+                    let offset_y = if max_y < selected_y + selected_h {
                         selected_y + selected_h - max_y
                     } else {
                         0f64
                     };
 
-                    for (i, cell_size) in cell_size_list.iter().enumerate() {
+                    for cell_size in cell_size_list.iter() {
                         let x = start_x + (cell_size.get_start_col() * 20) as f64 + gap_x;
                         let y = start_y + (cell_size.get_start_row() * 20) as f64 + gap_y;
                         let w = (cell_size.get_width() * 20) as f64 - gap_x * 2.0;
@@ -243,7 +388,7 @@ impl App {
                                 y: max_y - (y + h - offset_y),
                                 width: w,
                                 height: h,
-                                color: if i+1 != selected_index {
+                                color: if cell_size.get_card_index() + 1 != selected_index {
                                     *self.data.metadata.get_card_color(cell_size.get_card_type())
                                 } else {
                                     Color::Yellow
@@ -267,21 +412,34 @@ impl App {
             // 底部状态栏
             let status_bar_text = match self.tui_state {
                 TuiState::Edit(_) => "Shortcuts: Go Back(Esc)",
-                TuiState::Select(_) => "Shortcuts: Move Cursor(↑↓) Select(↵) Move Card(JK) Reshape Card(R) Create New(N) Delete(D) Quit(Q)",
+                TuiState::Select(_) => "Shortcuts: Move Cursor(↑↓) Select(↵) Move Card(JK) Reshape Card(R) Create New(N) Delete(D) Theme(T) Save(S) Quit(Q)",
                 TuiState::Create(_, _, _) => "Shortcuts: Move Cursor(↑↓) Confirm Create(↵) Cancel(Esc)",
                 TuiState::Delete(_) => "Shortcuts: Confirm Delete(↵) Cancel(Esc)",
+                TuiState::ConfirmQuit(_) => "Shortcuts: Save and Quit(S) Discard and Quit(D) Cancel(Esc)",
+                TuiState::Conflict(_) => "Shortcuts: Overwrite(O) Reload(R) Merge(M) Cancel(Esc)",
+                TuiState::Merge(_, _) => "Shortcuts: Move Cursor(↑↓) Switch Side(Space) Apply and Save(↵) Back(Esc)",
+                TuiState::Repair => "Shortcuts: Open Cards When It Parses(Esc) Quit Without Saving(Ctrl-Q)",
                 TuiState::Quit => "Bye~"
             };
 
-            let status_bar = Paragraph::new(Span::raw(status_bar_text))
-                .block(Block::default().borders(Borders::ALL));
-            f.render_widget(status_bar, Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([
-                    Constraint::Min(0),
-                    Constraint::Length(3),
-                ])
-            .split(size)[1]);
+            // A message from the last action replaces the shortcuts until the next key
+            let status_bar_span = match &self.message {
+                Some((message, color)) => Span::styled(message.as_str(), Style::default().fg(*color)),
+                None => Span::raw(status_bar_text),
+            };
+
+            let file_title = if self.dirty {
+                format!(" {} [+] ", self.source_file)
+            } else {
+                format!(" {} ", self.source_file)
+            };
+
+            let status_bar = Paragraph::new(status_bar_span)
+                .block(Block::default()
+                    .title(text::Line::from(file_title).left_aligned())
+                    .title(text::Line::from(format!(" {} ", self.data.metadata.get_theme_name())).right_aligned())
+                    .borders(Borders::ALL));
+            f.render_widget(status_bar, rows[1]);
 
             if let TuiState::Create(_, card_index, shape_index) = self.tui_state {
 
@@ -341,18 +499,195 @@ impl App {
                 f.render_widget(block, center_area);
             }
 
+            if let TuiState::ConfirmQuit(_) = self.tui_state {
+
+                let center_area = centered_rect(30, 15, size);
+
+                let block = Block::default()
+                    .title("Quit")
+                    .title_bottom(text::Line::from("Save(S)").left_aligned())
+                    .title_bottom(text::Line::from("Discard(D)").centered())
+                    .title_bottom(text::Line::from("Cancel(Esc)").right_aligned())
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Yellow));
+
+                let question = Paragraph::new("There are unsaved changes.")
+                    .wrap(Wrap { trim: true })
+                    .block(block);
+                f.render_widget(Clear, center_area);
+                f.render_widget(question, center_area);
+            }
+
+            if let TuiState::Conflict(_) = self.tui_state {
+
+                let center_area = centered_rect(40, 15, size);
+
+                let block = Block::default()
+                    .title("Conflict")
+                    .title_bottom(text::Line::from("Overwrite(O)").left_aligned())
+                    .title_bottom(text::Line::from("Reload(R)").centered())
+                    .title_bottom(text::Line::from("Merge(M)").right_aligned())
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Red));
+
+                let question = Paragraph::new(format!("{} was changed by someone else since it was opened.", self.source_file))
+                    .wrap(Wrap { trim: true })
+                    .block(block);
+                f.render_widget(Clear, center_area);
+                f.render_widget(question, center_area);
+            }
+
+            if let (TuiState::Merge(_, cursor), Some(merge)) = (&self.tui_state, &self.merge) {
+
+                let center_area = centered_rect(60, 50, size);
+
+                let block = Block::default()
+                    .title("Merge")
+                    .title_bottom(text::Line::from("[here] keeps this version, [disk] takes the file's").left_aligned())
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Cyan));
+
+                let items: Vec<ListItem> = merge.entries
+                    .iter()
+                    .enumerate()
+                    .map(|(i, entry)| {
+                        let style = if i == *cursor {
+                            Style::default().fg(Color::Yellow)
+                        } else if entry.is_conflict() {
+                            Style::default().fg(Color::Red)
+                        } else {
+                            Style::default().fg(Color::White)
+                        };
+                        ListItem::new(Span::styled(entry.describe(&self.data, &merge.disk), style))
+                    })
+                    .collect();
+
+                let list = List::new(items).block(block);
+                f.render_widget(Clear, center_area);
+                f.render_widget(list, center_area);
+            }
+
         })?;
         // ...
         Ok(())
     }
 
     fn handle_input(&mut self) -> Result<()> {
+        if !event::poll(WATCH_INTERVAL)? {
+            self.watch_files();
+            return Ok(());
+        }
         if let Event::Key(key_event) = event::read()? {
-            self.process_key_event(key_event)?;
+            self.message = None;
+            // Keep the TUI running and show what went wrong
+            if let Err(e) = self.process_key_event(key_event) {
+                write_info!(format!("Error: {:#}", e));
+                self.message = Some((format!("Error: {:#}", e), Color::Red));
+            }
         }
         Ok(())
     }
 
+    // Returns false when the file changed on disk and a conflict needs resolving first
+    fn save(&mut self, selected_index: usize) -> Result<bool> {
+        if changed_on_disk(&self.source_file, self.fingerprint.as_ref()) {
+            self.tui_state = TuiState::Conflict(selected_index);
+            return Ok(false);
+        }
+        self.write()?;
+        Ok(true)
+    }
+
+    fn write(&mut self) -> Result<()> {
+        save_data_to_file(&self.data, &self.source_file)?;
+        self.fingerprint = Fingerprint::of_file(&self.source_file);
+        self.base = self.data.clone();
+        self.dirty = false;
+        self.message = Some((format!("Saved {}", self.source_file), Color::Green));
+        Ok(())
+    }
+
+    // Replace the open document with the file on disk, dropping local changes.
+    // The selected card stays selected if it is still there.
+    fn reload(&mut self, selected_index: usize) -> Result<()> {
+        let fingerprint = Fingerprint::of_file(&self.source_file);
+        let data = Data::load(self.data.metadata.clone(), &self.source_file)?;
+        let selected_index = match_cards(&self.data, &data)
+            .into_iter()
+            .find(|(from, _)| *from == selected_index)
+            .map_or(selected_index, |(_, to)| to);
+
+        self.data = data;
+        self.base = self.data.clone();
+        self.fingerprint = fingerprint;
+        self.dirty = false;
+        self.select(selected_index);
+        Ok(())
+    }
+
+    // Pick up changes made to the content file or metadata by other programs
+    fn watch_files(&mut self) {
+        if let Some(metadata_file) = self.metadata_file.clone() {
+            if changed_on_disk(&metadata_file, self.metadata_fingerprint.as_ref()) {
+                self.metadata_fingerprint = Fingerprint::of_file(&metadata_file);
+                match Config::load(&metadata_file) {
+                    Ok(config) => {
+                        let mut metadata = config.get_metadata().clone();
+                        let _ = metadata.set_theme(self.data.metadata.get_theme_name());
+                        self.base.metadata = metadata.clone();
+                        self.data.metadata = metadata;
+                        self.message = Some((format!("Reloaded {}", metadata_file), Color::Green));
+                    }
+                    Err(e) => {
+                        self.message = Some((format!("Error: {:#}", e), Color::Red));
+                    }
+                }
+            }
+        }
+
+        if !changed_on_disk(&self.source_file, self.fingerprint.as_ref()) {
+            self.reported = None;
+            return;
+        }
+        let on_disk = Fingerprint::of_file(&self.source_file);
+        if on_disk.is_some() && self.reported == on_disk {
+            return;
+        }
+
+        match (&self.tui_state, on_disk.is_some()) {
+            (TuiState::Select(selected_index), true) if !self.dirty => {
+                let selected_index = *selected_index;
+                match self.reload(selected_index) {
+                    Ok(()) => {
+                        self.message = Some((format!("Reloaded {}, it changed on disk", self.source_file), Color::Green));
+                    }
+                    Err(e) => {
+                        self.reported = on_disk;
+                        self.message = Some((format!("Error: {:#}", e), Color::Red));
+                    }
+                }
+            }
+            (_, true) => {
+                self.reported = on_disk;
+                self.message = Some((
+                    format!("{} changed on disk, saving will ask how to combine the changes", self.source_file),
+                    Color::Yellow,
+                ));
+            }
+            (_, false) if self.reported.is_none() && self.fingerprint.is_some() => {
+                self.reported = self.fingerprint.clone();
+                self.message = Some((format!("{} was removed from disk", self.source_file), Color::Yellow));
+            }
+            _ => (),
+        }
+    }
+
+    fn select(&mut self, selected_index: usize) {
+        let selected_index = selected_index.min(self.data.items.len() - 1);
+        self.tui_state = TuiState::Select(selected_index);
+        self.text_area = TextArea::new(self.data.items[selected_index].get_lines().to_vec());
+    }
+
     fn process_key_event(&mut self, key_event: KeyEvent) -> Result<()> {
         match self.tui_state {
             TuiState::Edit(selected_index) => self.edit_mode(key_event, selected_index),
@@ -361,6 +696,10 @@ impl App {
                 self.create_mode(key_event, selected_index, card_index, shape_index)
             }
             TuiState::Delete(selected_index) => self.delete_mode(key_event, selected_index),
+            TuiState::ConfirmQuit(selected_index) => self.quit_mode(key_event, selected_index),
+            TuiState::Conflict(selected_index) => self.conflict_mode(key_event, selected_index),
+            TuiState::Merge(selected_index, cursor) => self.merge_mode(key_event, selected_index, cursor),
+            TuiState::Repair => self.repair_mode(key_event),
             _ => Ok(()),
         }
     }
@@ -424,11 +763,14 @@ impl App {
                     Ok(()) => {
                         self.oops_count = 0;
                         self.tui_state = TuiState::Select(selected_index);
-                        self.data
+                        let item = self
+                            .data
                             .items
                             .get_mut(selected_index)
-                            .context("No item found!")?
-                            .set_lines_and_format(self.text_area.lines());
+                            .context("No item found!")?;
+                        let before = item.get_lines().to_vec();
+                        item.set_lines_and_format(self.text_area.lines());
+                        self.dirty |= item.get_lines() != before.as_slice();
                     }
                     Err(_) => {
                         // self.message = msg.to_string();
@@ -447,28 +789,42 @@ impl App {
         write_info!(format!("> Select - index: {}", selected_index));
         match key_event.code {
             KeyCode::Char('q') | KeyCode::Char('Q') => {
-                self.tui_state = TuiState::Quit;
+                self.tui_state = if self.dirty {
+                    TuiState::ConfirmQuit(selected_index)
+                } else {
+                    TuiState::Quit
+                };
+            }
+            KeyCode::Char('s') | KeyCode::Char('S') => {
+                self.save(selected_index)?;
             }
             KeyCode::Char('n') | KeyCode::Char('N') => {
                 self.tui_state = TuiState::Create(selected_index, 0, 999);
             }
-            KeyCode::Char('d') | KeyCode::Char('D') => {
-                if selected_index != 0 {
-                    self.tui_state = TuiState::Delete(selected_index);
+            KeyCode::Char('t') | KeyCode::Char('T') => {
+                self.data.metadata.next_theme()?;
+                let theme = self.data.metadata.get_theme_name();
+                write_info!(format!("> Theme - {}", theme));
+                if let Err(e) = remember_theme(&self.source_file, theme) {
+                    write_info!(format!("Failed to remember theme: {}", e));
                 }
             }
-            KeyCode::Char('j') | KeyCode::Char('J') => {
-                if selected_index < self.data.items.len() - 1 {
+            KeyCode::Char('d') | KeyCode::Char('D')
+                if selected_index != 0 => {
+                    self.tui_state = TuiState::Delete(selected_index);
+                }
+            KeyCode::Char('j') | KeyCode::Char('J')
+                if selected_index < self.data.items.len() - 1 => {
                     self.data.items.swap(selected_index, selected_index + 1);
                     self.tui_state = TuiState::Select(selected_index + 1);
+                    self.dirty = true;
                 }
-            }
-            KeyCode::Char('k') | KeyCode::Char('K') => {
-                if selected_index > 0 {
+            KeyCode::Char('k') | KeyCode::Char('K')
+                if selected_index > 0 => {
                     self.data.items.swap(selected_index, selected_index - 1);
                     self.tui_state = TuiState::Select(selected_index - 1);
+                    self.dirty = true;
                 }
-            }
             KeyCode::Char('r') | KeyCode::Char('R') => {
                 let item = self
                     .data
@@ -476,7 +832,7 @@ impl App {
                     .get_mut(selected_index)
                     .context("No item found!")?;
 
-                if item.get_title() != "Section" {
+                if item.get_title() != "Section" && selected_index != 0 {
                     let mut shape_index = self.data.metadata.index_of_shape(item.get_shape());
                     shape_index = (shape_index + 1) % self.data.metadata.count_shapes();
 
@@ -485,12 +841,14 @@ impl App {
                         .metadata
                         .get_shape(shape_index)
                         .context("No item shape found!")?;
-                    item.set_shape(new_shape.to_string());
+                    item.set_shape(new_shape.to_string())?;
                     write_info!(format!(
                         "> Reshape - {}-{}",
                         item.get_title(),
                         item.get_shape()
                     ));
+                    self.text_area = TextArea::new(item.get_lines().to_vec());
+                    self.dirty = true;
                 }
             }
             KeyCode::Enter => {
@@ -499,20 +857,18 @@ impl App {
                 self.text_area =
                     TextArea::new(self.data.items[selected_index].get_lines().to_vec());
             }
-            KeyCode::Up => {
-                if selected_index > 0 {
+            KeyCode::Up
+                if selected_index > 0 => {
                     self.tui_state = TuiState::Select(selected_index - 1);
                     self.text_area =
                         TextArea::new(self.data.items[selected_index - 1].get_lines().to_vec());
                 }
-            }
-            KeyCode::Down => {
-                if selected_index < self.data.items.len() - 1 {
+            KeyCode::Down
+                if selected_index < self.data.items.len() - 1 => {
                     self.tui_state = TuiState::Select(selected_index + 1);
                     self.text_area =
                         TextArea::new(self.data.items[selected_index + 1].get_lines().to_vec());
                 }
-            }
             _ => (),
         }
         Ok(())
@@ -540,6 +896,7 @@ impl App {
 
                     // insert to data
                     self.data.items.insert(selected_index + 1, new_item);
+                    self.dirty = true;
                     write_info!(format!("create - idx: {}", selected_index + 1));
 
                     self.tui_state = TuiState::Edit(selected_index + 1);
@@ -564,7 +921,7 @@ impl App {
             }
             KeyCode::Down => {
                 if shape_index == 999 {
-                    if card_index < self.data.items.len() - 1 {
+                    if card_index < self.data.metadata.get_cards().len() - 1 {
                         self.tui_state = TuiState::Create(selected_index, card_index + 1, 999);
                     }
                 } else {
@@ -581,15 +938,62 @@ impl App {
 
     fn delete_mode(&mut self, key_event: KeyEvent, selected_index: usize) -> Result<()> {
         match key_event.code {
-            KeyCode::Enter => {
-                if selected_index != 0 {
+            KeyCode::Enter
+                if selected_index != 0 => {
                     // remove deleted item
                     self.data.items.remove(selected_index);
+                    self.dirty = true;
 
                     self.tui_state = TuiState::Select(selected_index - 1);
                     self.text_area =
                         TextArea::new(self.data.items[selected_index - 1].get_lines().to_vec());
                 }
+            KeyCode::Esc => {
+                self.tui_state = TuiState::Select(selected_index);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn quit_mode(&mut self, key_event: KeyEvent, selected_index: usize) -> Result<()> {
+        match key_event.code {
+            KeyCode::Char('s') | KeyCode::Char('S') | KeyCode::Enter => {
+                // Stay in the TUI if the save fails, the error shows in the status bar
+                self.tui_state = TuiState::Select(selected_index);
+                if self.save(selected_index)? {
+                    self.tui_state = TuiState::Quit;
+                }
+            }
+            KeyCode::Char('d') | KeyCode::Char('D') => {
+                self.tui_state = TuiState::Quit;
+            }
+            KeyCode::Esc | KeyCode::Char('c') | KeyCode::Char('C') => {
+                self.tui_state = TuiState::Select(selected_index);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn conflict_mode(&mut self, key_event: KeyEvent, selected_index: usize) -> Result<()> {
+        match key_event.code {
+            KeyCode::Char('o') | KeyCode::Char('O') => {
+                self.tui_state = TuiState::Select(selected_index);
+                self.write()?;
+            }
+            KeyCode::Char('r') | KeyCode::Char('R') => {
+                self.tui_state = TuiState::Select(selected_index);
+                self.reload(selected_index)?;
+                self.message = Some((format!("Reloaded {}", self.source_file), Color::Green));
+            }
+            KeyCode::Char('m') | KeyCode::Char('M') => {
+                self.tui_state = TuiState::Select(selected_index);
+                let fingerprint = Fingerprint::of_file(&self.source_file);
+                let disk = Data::load(self.data.metadata.clone(), &self.source_file)?;
+                let entries = merge_entries(&self.base, &self.data, &disk);
+                self.merge = Some(Merge { disk, fingerprint, entries });
+                self.tui_state = TuiState::Merge(selected_index, 0);
             }
             KeyCode::Esc => {
                 self.tui_state = TuiState::Select(selected_index);
@@ -598,6 +1002,100 @@ impl App {
         }
         Ok(())
     }
+
+    fn merge_mode(&mut self, key_event: KeyEvent, selected_index: usize, cursor: usize) -> Result<()> {
+        let merge = self.merge.as_mut().context("No merge in progress")?;
+        match key_event.code {
+            KeyCode::Up if cursor > 0 => {
+                self.tui_state = TuiState::Merge(selected_index, cursor - 1);
+            }
+            KeyCode::Down if cursor + 1 < merge.entries.len() => {
+                self.tui_state = TuiState::Merge(selected_index, cursor + 1);
+            }
+            KeyCode::Char(' ') | KeyCode::Left | KeyCode::Right | KeyCode::Tab => {
+                if let Some(entry) = merge.entries.get_mut(cursor) {
+                    entry.toggle();
+                }
+            }
+            KeyCode::Enter => {
+                let merge = self.merge.take().context("No merge in progress")?;
+                self.data = apply_merge(&self.data, &merge.disk, &merge.entries);
+                // The merged document already includes what is on disk
+                self.fingerprint = merge.fingerprint;
+                self.select(selected_index);
+                self.write()?;
+            }
+            KeyCode::Esc => {
+                self.merge = None;
+                self.tui_state = TuiState::Conflict(selected_index);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn repair_mode(&mut self, key_event: KeyEvent) -> Result<()> {
+        let repair = self.repair.as_mut().context("Nothing to repair")?;
+        match key_event.code {
+            KeyCode::Char('q') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.tui_state = TuiState::Quit;
+            }
+            KeyCode::Esc => {
+                let mut text = self.text_area.lines().join("\n");
+                if repair.trailing_newline {
+                    text.push('\n');
+                }
+                match Data::parse(self.data.metadata.clone(), &text, &self.source_file) {
+                    Ok(data) => {
+                        // The fixed text is not on disk yet, saving writes it as typed
+                        self.oops_count = 0;
+                        self.repair = None;
+                        self.data = data;
+                        self.base = self.data.clone();
+                        self.dirty = true;
+                        self.select(0);
+                        self.message = Some((format!("{} parses again, save to keep the fix", self.source_file), Color::Green));
+                    }
+                    Err(e) => {
+                        let errors = e.downcast::<LoadErrors>()?;
+                        self.oops_count += 1;
+                        jump_to_error(&mut self.text_area, &errors);
+                        repair.errors = Some(errors);
+                    }
+                }
+            }
+            _ => {
+                if self.text_area.input(key_event) {
+                    let text = self.text_area.lines().join("\n");
+                    repair.errors = Data::parse(self.data.metadata.clone(), &text, &self.source_file)
+                        .err()
+                        .map(|e| e.downcast::<LoadErrors>())
+                        .transpose()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Put the cursor where the first error is, which highlights its line
+fn jump_to_error(text_area: &mut TextArea, errors: &LoadErrors) {
+    if let Some((line, column)) = errors.first_position() {
+        let row = u16::try_from(line - 1).unwrap_or(u16::MAX);
+        let col = u16::try_from(column - 1).unwrap_or(u16::MAX);
+        text_area.move_cursor(CursorMove::Jump(row, col));
+    }
+}
+
+// An error as shown in the problem list, without the file name repeated
+fn problem_lines(error: &LoadError) -> Vec<text::Line<'static>> {
+    let mut error = error.clone();
+    error.file = None;
+    error
+        .to_string()
+        .lines()
+        .map(|line| text::Line::styled(line.to_string(), Style::default().fg(Color::Red)))
+        .collect()
 }
 
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -635,10 +1133,5 @@ impl Drop for App {
         disable_raw_mode().expect("Failed to disable raw mode");
         crossterm::execute!(io::stdout(), LeaveAlternateScreen)
             .expect("Failed to leave alternate screen");
-
-        // Save data to file
-        if let Err(e) = save_data_to_file(&self.data, &self.source_file) {
-            eprintln!("Error saving data: {}", e);
-        }
     }
 }
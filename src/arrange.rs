@@ -4,9 +4,13 @@ pub struct CellSize {
     start_col: usize,
     width: usize,
     height: usize,
+    card_index: usize,
     card_type: String,
 }
 
+/// Rows and columns of the grid cards are arranged on.
+pub const GRID_SIZE: (usize, usize) = (50, 8);
+
 pub fn arrange_grid(grid_size: (usize, usize), cell_list: &[String]) -> Vec<CellSize> {
     let (grow, gcol) = grid_size;
 
@@ -15,28 +19,78 @@ pub fn arrange_grid(grid_size: (usize, usize), cell_list: &[String]) -> Vec<Cell
 
     let mut cell_size_list = vec![];
 
-    for cell in cell_list.iter() {
-        let (cell_type, cell_shape) = cell.split_once('-').unwrap();
-        let cell_size = add_cell(&mut grid, cell_shape, cell_type, 'x', start_row);
+    for (card_index, cell) in cell_list.iter().enumerate() {
+        let (cell_type, cell_shape) = cell.rsplit_once('-').unwrap_or((cell, ""));
+        let mut cell_size = add_cell(&mut grid, cell_shape, cell_type, 'x', start_row);
         if cell_size.start_col == 999 {
             continue;
         }
+        cell_size.card_index = card_index;
         start_row = cell_size.start_row;
         cell_size_list.push(cell_size);
     }
 
-    // let grid_str = grid
-    // .into_iter()
-    //.filter(|row| !row.iter().all(|c| *c == ' '))
-    // .map(|row| row.into_iter().collect::<String>())
-    // .collect::<Vec<_>>()
-    // .join("\n");
+    cell_size_list
+}
 
-    // println!("{}", grid_str);
+/// Rows and columns of a shape such as "2x4", `None` when it is not one.
+pub fn parse_shape(shape: &str) -> Option<(usize, usize)> {
+    let (rows, cols) = shape.split_once('x')?;
+    match (rows.parse(), cols.parse()) {
+        (Ok(rows), Ok(cols)) if rows > 0 && cols > 0 => Some((rows, cols)),
+        _ => None,
+    }
+}
 
-    cell_size_list
+/// Draw arranged cells as ASCII boxes, labeling each one by its `card_index`.
+pub fn draw_grid(grid_cols: usize, cell_size_list: &[CellSize], labels: &[String]) -> Vec<String> {
+    let cell_w = 10;
+    let cell_h = 2;
+
+    let rows = cell_size_list
+        .iter()
+        .map(|c| c.start_row + c.height)
+        .max()
+        .unwrap_or(0);
+
+    let mut canvas = vec![vec![' '; grid_cols * cell_w + 1]; rows * cell_h + 1];
+
+    let bounds: Vec<(usize, usize, usize, usize)> = cell_size_list
+        .iter()
+        .map(|c| (
+            c.start_row * cell_h,
+            (c.start_row + c.height) * cell_h,
+            c.start_col * cell_w,
+            (c.start_col + c.width) * cell_w,
+        ))
+        .collect();
+
+    // Edges first, so that corners of neighbouring cells are not overdrawn
+    for &(top, bottom, left, right) in &bounds {
+        canvas[top][left..=right].fill('-');
+        canvas[bottom][left..=right].fill('-');
+        for row in canvas.iter_mut().take(bottom).skip(top + 1) {
+            row[left] = '|';
+            row[right] = '|';
+        }
+    }
 
-    // grid
+    for (cell, &(top, bottom, left, right)) in cell_size_list.iter().zip(&bounds) {
+        for (y, x) in [(top, left), (top, right), (bottom, left), (bottom, right)] {
+            canvas[y][x] = '+';
+        }
+
+        let label = labels.get(cell.card_index).map(String::as_str).unwrap_or("?");
+        let room = right - left - 2;
+        for (i, ch) in label.chars().take(room).enumerate() {
+            canvas[top + 1][left + 2 + i] = ch;
+        }
+    }
+
+    canvas
+        .into_iter()
+        .map(|row| row.into_iter().collect::<String>().trim_end().to_string())
+        .collect()
 }
 
 impl CellSize {
@@ -56,6 +110,10 @@ impl CellSize {
         self.height
     }
 
+    pub fn get_card_index(&self) -> usize {
+        self.card_index
+    }
+
     pub fn get_card_type(&self) -> &String {
         &self.card_type
     }
@@ -80,9 +138,9 @@ fn place_cell(
     height: usize,
     char: char,
 ) {
-    for r in row..row + height {
-        for c in col..col + width {
-            grid[r][c] = char;
+    for grid_row in grid.iter_mut().skip(row).take(height) {
+        for cell in grid_row.iter_mut().skip(col).take(width) {
+            *cell = char;
         }
     }
 }
@@ -95,7 +153,7 @@ fn try_place_cell(
     start_row: usize,
 ) -> (usize, usize) {
     for row in start_row..grid.len() {
-        for col in 0..=grid[0].len() - width {
+        for col in 0..=grid[0].len().saturating_sub(width) {
             if can_place_cell(grid, row, col, width, height) {
                 place_cell(grid, row, col, width, height, char);
                 return (row, col);
@@ -112,15 +170,15 @@ fn add_cell(
     cell_char: char,
     start_row: usize,
 ) -> CellSize {
-    let (num1, num2) = cell_shape.split_once('x').unwrap();
-    let num1: usize = num1.parse().unwrap();
-    let num2: usize = num2.parse().unwrap();
+    // Files are checked on load, but an edit can still leave a bad shape behind
+    let (num1, num2) = parse_shape(cell_shape).unwrap_or((2, 2));
     let (start_row, start_col) = try_place_cell(grid, num2, num1, cell_char, start_row);
     CellSize {
         start_row,
         start_col,
         width: num2,
         height: num1,
+        card_index: 0,
         card_type: cell_type.to_string(),
     }
 }
@@ -0,0 +1,420 @@
+use crate::app::App;
+use crate::arrange::{arrange_grid, draw_grid, GRID_SIZE};
+use crate::config::Config;
+use crate::data::{read_source, save_data_as, save_data_to_file, serialize_data, write_source, Data, DEFAULT_SHAPE, STDIO_PATH};
+use crate::diff::{diff_data, diff_layout, diff_matched};
+use crate::error::LoadErrors;
+use crate::format::Format;
+use crate::migrate::migrate_data;
+use crate::ops::{apply_ops, load_ops};
+use crate::storage::{list_backups, restore_backup};
+use crate::validate::validate_data;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::json;
+use similar::TextDiff;
+
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{anyhow, Result};
+
+/// A TUI tool for managing the content and layout of an obentou site.
+#[derive(Debug, Parser)]
+#[command(name = "obentou", version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    /// Content file to open in the TUI
+    file: Option<String>,
+
+    /// Theme for the TUI, remembered per content file
+    #[arg(long)]
+    theme: Option<String>,
+
+    /// Metadata file, searched next to the content file by default
+    #[arg(long, global = true)]
+    metadata: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Create a fresh content file
+    New {
+        /// Content file to create
+        file: String,
+        /// Add one card of each type
+        #[arg(long)]
+        seed: bool,
+        /// Overwrite the file if it exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// List the profile and every showcase card
+    List {
+        /// Content file
+        file: String,
+    },
+    /// Create a new card and insert it into the showcase
+    Add {
+        /// Content file
+        file: String,
+        /// Card type, e.g. Note or Link
+        card_type: String,
+        /// Card shape, e.g. 2x4
+        #[arg(long, default_value = DEFAULT_SHAPE)]
+        shape: String,
+        /// Insert position, appends to the end by default
+        #[arg(long)]
+        at: Option<usize>,
+    },
+    /// Remove a card
+    Remove {
+        /// Content file
+        file: String,
+        /// Card index as shown by `list`
+        index: usize,
+    },
+    /// Move a card to another position
+    Move {
+        /// Content file
+        file: String,
+        /// Card index as shown by `list`
+        from: usize,
+        /// Target card index
+        to: usize,
+    },
+    /// Change the shape of a card
+    Reshape {
+        /// Content file
+        file: String,
+        /// Card index as shown by `list`
+        index: usize,
+        /// New card shape, e.g. 2x4
+        shape: String,
+    },
+    /// Apply a JSON or TOML file of operations, all or nothing
+    Apply {
+        /// Operations file
+        ops: String,
+        /// Content file
+        file: String,
+    },
+    /// Check the profile and every card against metadata
+    Validate {
+        /// Content file
+        file: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Rewrite content files into canonical form
+    Fmt {
+        /// Content files
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Only report files that are not canonical, with a diff
+        #[arg(long)]
+        check: bool,
+    },
+    /// Show card-level changes between two content files
+    Diff {
+        /// Old content file
+        old: String,
+        /// New content file
+        new: String,
+        /// Also show which grid cells moved
+        #[arg(long)]
+        layout: bool,
+    },
+    /// Translate a content file between JSON, YAML and TOML
+    Convert {
+        /// Content file to read
+        input: String,
+        /// File to write, in the format given by its extension
+        output: String,
+        /// Output format, overriding the extension; stdout is JSON otherwise
+        #[arg(long, value_enum)]
+        to: Option<Format>,
+    },
+    /// Upgrade a content file to the schema version of its metadata
+    Migrate {
+        /// Content file
+        file: String,
+        /// Print the changes without writing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the showcase layout as ASCII art
+    Layout {
+        /// Content file
+        file: String,
+    },
+    /// Roll a content file back to one of its backups
+    Restore {
+        /// Content file
+        file: String,
+        /// Backup number, 1 being the most recent
+        #[arg(default_value_t = 1)]
+        backup: usize,
+        /// List the available backups instead
+        #[arg(long)]
+        list: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+pub fn run(cli: Cli) -> Result<ExitCode> {
+    let command = match cli.command {
+        Some(command) => command,
+        None => {
+            let file = cli.file.unwrap_or_default();
+            if file == STDIO_PATH {
+                return Err(anyhow!("The TUI needs a content file, not stdin"));
+            }
+            let config = Config::locate(cli.metadata.as_deref(), &file)?;
+            let mut app = App::new(file, config, cli.theme)?;
+            app.run()?;
+            return Ok(ExitCode::SUCCESS);
+        }
+    };
+
+    let metadata = cli.metadata.as_deref();
+    match command {
+        Command::New { file, seed, force } => {
+            if !force && file != STDIO_PATH && Path::new(&file).exists() {
+                return Err(anyhow!("{} already exists, pass --force to overwrite it", file));
+            }
+            let config = Config::locate(metadata, &file)?;
+            let data = Data::create(config.get_metadata().clone(), seed)?;
+            save_data_to_file(&data, &file)?;
+        }
+        Command::List { file } => {
+            let data = load_data(&file, metadata)?;
+            for (i, item) in data.items.iter().enumerate() {
+                if i == 0 {
+                    match item.label() {
+                        Some(label) => println!("> {} ({})", item.get_title(), label),
+                        None => println!("> {}", item.get_title()),
+                    }
+                    continue;
+                }
+                match item.label() {
+                    Some(label) => println!("{}. {}-{} ({})", i, item.get_title(), item.get_shape(), label),
+                    None => println!("{}. {}-{}", i, item.get_title(), item.get_shape()),
+                }
+            }
+        }
+        Command::Add { file, card_type, shape, at } => {
+            let mut data = load_data(&file, metadata)?;
+            let idx = at.unwrap_or(data.items.len());
+            data.insert_card(idx, &card_type, &shape)?;
+            save_data_to_file(&data, &file)?;
+        }
+        Command::Remove { file, index } => {
+            let mut data = load_data(&file, metadata)?;
+            data.remove_card(index)?;
+            save_data_to_file(&data, &file)?;
+        }
+        Command::Move { file, from, to } => {
+            let mut data = load_data(&file, metadata)?;
+            data.move_card(from, to)?;
+            save_data_to_file(&data, &file)?;
+        }
+        Command::Reshape { file, index, shape } => {
+            let mut data = load_data(&file, metadata)?;
+            data.reshape_card(index, &shape)?;
+            save_data_to_file(&data, &file)?;
+        }
+        Command::Apply { ops, file } => {
+            if ops == STDIO_PATH && file == STDIO_PATH {
+                return Err(anyhow!("Only one of the ops and content files can be read from stdin"));
+            }
+            let ops = load_ops(&ops)?;
+            let mut data = load_data(&file, metadata)?;
+            apply_ops(&mut data, &ops.ops)?;
+            save_data_to_file(&data, &file)?;
+        }
+        Command::Validate { file, format } => {
+            let data = match load_data(&file, metadata) {
+                Ok(data) => data,
+                Err(e) => match (format, e.downcast_ref::<LoadErrors>()) {
+                    // Scripts get JSON even when the file does not load
+                    (OutputFormat::Json, Some(errors)) => {
+                        let output = json!({
+                            "file": file,
+                            "valid": false,
+                            "problems": [],
+                            "load_errors": errors.0.iter().map(|e| e.to_json()).collect::<Vec<_>>(),
+                        });
+                        println!("{}", serde_json::to_string_pretty(&output)?);
+                        return Ok(ExitCode::FAILURE);
+                    }
+                    _ => return Err(e),
+                },
+            };
+            let diagnostics = validate_data(&data);
+            match format {
+                OutputFormat::Text => {
+                    for d in &diagnostics {
+                        println!("{}:{}: card {} ({}): {}", file, d.path, d.index, d.card_type, d.message);
+                    }
+                    if diagnostics.is_empty() {
+                        println!("{}: ok", file);
+                    } else {
+                        println!("{}: {} problem(s) found", file, diagnostics.len());
+                    }
+                }
+                OutputFormat::Json => {
+                    let output = json!({
+                        "file": file,
+                        "valid": diagnostics.is_empty(),
+                        "problems": diagnostics.iter().map(|d| d.to_json()).collect::<Vec<_>>(),
+                        "load_errors": [],
+                    });
+                    println!("{}", serde_json::to_string_pretty(&output)?);
+                }
+            }
+            if !diagnostics.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Fmt { files, check } => {
+            let mut canonical = true;
+            for file in files {
+                let text = read_source(&file)?;
+                let config = Config::locate(metadata, &file)?;
+                let data = Data::parse(config.get_metadata().clone(), &text, &file)?;
+                let formatted = serialize_data(&data)?;
+                if check {
+                    if text != formatted {
+                        canonical = false;
+                        let diff = TextDiff::from_lines(&text, &formatted);
+                        print!("{}", diff.unified_diff().header(&file, &file));
+                    }
+                } else if text != formatted || file == STDIO_PATH {
+                    // Write the canonical text itself, saving would keep the original layout
+                    write_source(&file, &formatted)?;
+                }
+            }
+            if !canonical {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Diff { old, new, layout } => {
+            if old == STDIO_PATH && new == STDIO_PATH {
+                return Err(anyhow!("Only one of the files can be read from stdin"));
+            }
+            let old_data = load_data(&old, metadata)?;
+            let new_data = load_data(&new, metadata)?;
+
+            let changes = diff_data(&old_data, &new_data);
+            for change in &changes {
+                println!("{}", change);
+            }
+
+            let mut differs = !changes.is_empty();
+            if layout {
+                let moves = diff_layout(&old_data, &new_data);
+                for cell_move in &moves {
+                    println!("{}", cell_move);
+                }
+                differs |= !moves.is_empty();
+            }
+            if differs {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Convert { input, output, to } => {
+            let data = load_data(&input, metadata)?;
+            save_data_as(&data, &output, to.unwrap_or_else(|| Format::from_path(&output)))?;
+        }
+        Command::Migrate { file, dry_run } => {
+            let old_data = load_data(&file, metadata)?;
+            let mut new_data = old_data.clone();
+            let from = migrate_data(&mut new_data)?;
+            let to = new_data.get_version();
+
+            // Keep stdout for the document when it is written there
+            let report = |line: String| {
+                if file == STDIO_PATH && !dry_run {
+                    eprintln!("{}", line);
+                } else {
+                    println!("{}", line);
+                }
+            };
+            if from == to {
+                report(format!("{}: already at version {}", file, to));
+                return Ok(ExitCode::SUCCESS);
+            }
+            report(format!("{}: version {} -> {}", file, from, to));
+            // Steps keep cards in place, only a retyped card is a new one
+            let pairs: Vec<(usize, usize)> = (1..old_data.items.len())
+                .filter(|&i| old_data.items[i].get_title() == new_data.items[i].get_title())
+                .map(|i| (i, i))
+                .collect();
+            for change in diff_matched(&old_data, &new_data, &pairs) {
+                report(change.to_string());
+            }
+            if !dry_run {
+                save_data_to_file(&new_data, &file)?;
+            }
+        }
+        Command::Layout { file } => {
+            let data = load_data(&file, metadata)?;
+            let cards: Vec<String> = data.items
+                .iter()
+                .skip(1)
+                .map(|item| format!("{}-{}", item.get_title(), item.get_shape()))
+                .collect();
+            let labels: Vec<String> = data.items
+                .iter()
+                .enumerate()
+                .skip(1)
+                .map(|(i, item)| format!("{}.{}", i, item.get_title()))
+                .collect();
+
+            let cell_size_list = arrange_grid(GRID_SIZE, &cards);
+            for line in draw_grid(GRID_SIZE.1, &cell_size_list, &labels) {
+                println!("{}", line);
+            }
+
+            let unplaced: Vec<usize> = (0..cards.len())
+                .filter(|i| !cell_size_list.iter().any(|c| c.get_card_index() == *i))
+                .collect();
+            if !unplaced.is_empty() {
+                println!();
+                println!("Unplaced cards:");
+                for i in unplaced {
+                    println!("  {}. {}", i + 1, cards[i]);
+                }
+            }
+        }
+        Command::Restore { file, backup, list } => {
+            if file == STDIO_PATH {
+                return Err(anyhow!("Stdin has no backups to restore"));
+            }
+            if list {
+                for (n, path) in list_backups(&file) {
+                    println!("{}. {}", n, path.display());
+                }
+            } else {
+                let path = restore_backup(&file, backup)?;
+                println!("Restored {} from {}", file, path.display());
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn load_data(filename: &str, metadata: Option<&str>) -> Result<Data> {
+    let config = Config::locate(metadata, filename)?;
+    Data::load(config.get_metadata().clone(), filename)
+}
@@ -1,91 +1,148 @@
 use anyhow::{Context, Result};
-use std::collections::HashMap;
-use toml;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::{Path, PathBuf};
 
 use ratatui::style::Color;
 
+use crate::arrange::parse_shape;
 use crate::data::MetaData;
+use crate::error::{toml_position, LoadError, LoadErrors};
+use crate::field::FieldSpec;
+use crate::migrate::{Migration, FIRST_VERSION};
+
+const METADATA_FILE: &str = "metadata.toml";
+
+// Built-in metadata used when no metadata.toml can be found
+const DEFAULT_METADATA: &str = include_str!("../metadata.toml");
 
 // Define a struct to hold the configuration data
 pub struct Config {
     metadata: MetaData,
+    // Where the metadata was read from, `None` for the built-in default
+    path: Option<String>,
 }
 
 impl Config {
+    /// Load metadata from `explicit` if given, otherwise look next to the content
+    /// file, then in the project root, then in `$XDG_CONFIG_HOME/obentou/`, and
+    /// finally fall back to the built-in default.
+    pub fn locate(explicit: Option<&str>, content_file: &str) -> Result<Config> {
+        if let Some(filename) = explicit {
+            return Config::load(filename);
+        }
+
+        match find_metadata(content_file) {
+            Some(path) => Config::load(&path.to_string_lossy()),
+            None => Ok(Config::parse(DEFAULT_METADATA)?),
+        }
+    }
+
     pub fn load(filename: &str) -> Result<Config> {
-        let metadata =
-            std::fs::read_to_string(filename).with_context(|| "Failed to read config file")?;
-        let parsed: toml::Value =
-            toml::from_str(&metadata).with_context(|| "Failed to parse config file")?;
-
-        let cards: Vec<String> = parsed["Cards"]["types"]
-            .as_array()
-            .context("Invalid 'types' format")?
-            .iter()
-            .map(|s| {
-                s.as_str()
-                    .context("Invalid string in 'types'")
-                    .unwrap()
-                    .to_string()
-            })
-            .collect();
-
-        let shapes: Vec<String> = parsed["Cards"]["shapes"]
-            .as_array()
-            .context("Invalid 'shapes' format")?
-            .iter()
-            .map(|s| {
-                s.as_str()
-                    .context("Invalid string in 'shapes'")
-                    .unwrap()
-                    .to_string()
-            })
-            .collect();
-
-        let mut fields: HashMap<String, Vec<String>> = HashMap::new();
-        for (key, value) in parsed["Cards"]["Fields"]
-            .as_table()
-            .context("Invalid 'Fields' format")?
-        {
-            let field_types: Vec<String> = value
-                .as_array()
-                .context(format!("Invalid format for field '{}'", key))?
-                .iter()
-                .map(|s| {
-                    s.as_str()
-                        .context("Invalid string in field types")
-                        .unwrap()
-                        .to_string()
-                })
-                .collect();
-            fields.insert(key.to_string(), field_types);
+        let metadata = std::fs::read_to_string(filename)
+            .with_context(|| format!("Failed to read config file {}", filename))?;
+        let mut config = Config::parse(&metadata).map_err(|e| e.in_file(filename))?;
+        config.path = Some(filename.to_string());
+        Ok(config)
+    }
+
+    /// Parse metadata, reporting every problem in it at once.
+    pub fn parse(metadata: &str) -> Result<Config, LoadErrors> {
+        let parsed: toml::Table =
+            toml::from_str(metadata).map_err(|e| LoadError::from_toml(&e, metadata))?;
+
+        let mut errors = vec![];
+
+        let cards_table = table(&parsed, "Cards", "Cards", &mut errors);
+        let cards = cards_table
+            .map(|t| string_list(t.get("types"), "Cards.types", &mut errors))
+            .unwrap_or_default();
+
+        let shapes = cards_table
+            .map(|t| string_list(t.get("shapes"), "Cards.shapes", &mut errors))
+            .unwrap_or_default();
+        for (i, shape) in shapes.iter().enumerate() {
+            if parse_shape(shape).is_none() {
+                errors.push(
+                    LoadError::new(&format!("Invalid shape \"{}\"", shape))
+                        .path(&format!("Cards.shapes[{}]", i))
+                        .hint("Shapes are rows x columns, like \"2x4\""),
+                );
+            }
         }
 
-        let mut themes: HashMap<String, Vec<Color>> = HashMap::new();
-        for (key, value) in parsed["Themes"]
-            .as_table()
-            .context("Invalid 'Themes' format")?
-        {
-            let theme_colors: Vec<Color> = value
-                .as_array()
-                .context(format!("Invalid format for theme '{}'", key))?
-                .iter()
-                .map(|v| {
-                    v.as_integer()
-                        .context("Invalid integer in theme colors")
-                        .unwrap() as u8
-                })
-                .map(|i| Color::Indexed(i))
-                .collect();
-            themes.insert(key.to_string(), theme_colors);
+        let mut fields: HashMap<String, Vec<FieldSpec>> = HashMap::new();
+        if let Some(table) = cards_table.and_then(|t| self::table(t, "Fields", "Cards.Fields", &mut errors)) {
+            for (key, value) in table {
+                let path = format!("Cards.Fields.{}", key);
+                let Some(values) = value.as_array() else {
+                    errors.push(LoadError::new(&format!("Expected an array of fields, found {}", value.type_str())).path(&path));
+                    continue;
+                };
+                let mut specs = vec![];
+                for (i, v) in values.iter().enumerate() {
+                    match FieldSpec::from_toml(v) {
+                        Ok(spec) => specs.push(spec),
+                        Err(message) => {
+                            let mut error = LoadError::new(&message).path(&format!("{}[{}]", path, i));
+                            if !v.is_table() {
+                                error = error.hint("Declare a field as \"name\", \"name?\" or { name = \"name\", type = \"string\" }");
+                            }
+                            errors.push(error);
+                        }
+                    }
+                }
+                fields.insert(key.to_string(), specs);
+            }
         }
 
-        let chosen_theme = themes.get("mondrian").context("No such a theme")?.clone();
-        let theme: HashMap<String, Color> = cards
-            .clone()
-            .into_iter()
-            .zip(chosen_theme.into_iter())
-            .collect();
+        let mut themes: BTreeMap<String, Vec<Color>> = BTreeMap::new();
+        if let Some(table) = table(&parsed, "Themes", "Themes", &mut errors) {
+            for (key, value) in table {
+                let path = format!("Themes.{}", key);
+                let Some(values) = value.as_array() else {
+                    errors.push(LoadError::new(&format!("Expected an array of colors, found {}", value.type_str())).path(&path));
+                    continue;
+                };
+                let mut theme_colors = vec![];
+                for (i, v) in values.iter().enumerate() {
+                    match v.as_integer().and_then(|c| u8::try_from(c).ok()) {
+                        Some(c) => theme_colors.push(Color::Indexed(c)),
+                        None => errors.push(
+                            LoadError::new(&format!("Invalid color {}", v))
+                                .path(&format!("{}[{}]", path, i))
+                                .hint("Colors are terminal palette indices from 0 to 255"),
+                        ),
+                    }
+                }
+                themes.insert(key.to_string(), theme_colors);
+            }
+        }
+
+        let theme = match parsed.get("theme") {
+            Some(theme) => match theme.as_str() {
+                Some(name) if themes.contains_key(name) => Some(name),
+                Some(name) => {
+                    errors.push(
+                        LoadError::new(&format!("No such theme: {}", name))
+                            .path("theme")
+                            .hint(&format!("Pick one of the [Themes] tables: {}", themes.keys().cloned().collect::<Vec<_>>().join(", "))),
+                    );
+                    None
+                }
+                None => {
+                    errors.push(LoadError::new(&format!("Expected a theme name, found {}", theme.type_str())).path("theme"));
+                    None
+                }
+            },
+            None if themes.contains_key("mondrian") => Some("mondrian"),
+            None => themes.keys().next().map(String::as_str),
+        }
+        .unwrap_or_default()
+        .to_string();
+        if themes.is_empty() && errors.is_empty() {
+            errors.push(LoadError::new("No theme found").path("Themes").hint("Declare at least one theme under [Themes]"));
+        }
 
         /*let metadata = MetaData {
             cards,
@@ -93,14 +150,147 @@ impl Config {
             fields,
         };*/
 
+        let version = match parsed.get("version") {
+            Some(version) => match version.as_integer().and_then(|v| u64::try_from(v).ok()) {
+                Some(version) => version,
+                None => {
+                    errors.push(LoadError::new(&format!("Invalid version {}", version)).path("version").hint("Versions are whole numbers from 1 up"));
+                    FIRST_VERSION
+                }
+            },
+            None => FIRST_VERSION,
+        };
+
+        let mut migrations = vec![];
+        match parsed.get("Migrations") {
+            Some(toml::Value::Array(steps)) => {
+                for (i, step) in steps.iter().enumerate() {
+                    match Migration::from_toml(step) {
+                        Ok(step) => migrations.push((i, step)),
+                        Err(message) => errors.push(
+                            LoadError::new(&message)
+                                .path(&format!("Migrations[{}]", i))
+                                .hint("A step needs a version and at least one of rename, remove, default or retype"),
+                        ),
+                    }
+                }
+            }
+            Some(_) => errors.push(LoadError::new("Expected an array of tables").path("Migrations").hint("Declare each step under [[Migrations]]")),
+            None => {}
+        }
+        // A type that a step retypes away may be gone from Cards.types
+        let retired: Vec<&String> = migrations.iter().filter(|(_, m)| m.retype.is_some()).filter_map(|(_, m)| m.card.as_ref()).collect();
+        for (i, step) in &migrations {
+            let card = step.card.as_ref().filter(|c| *c != "Profile" && !retired.contains(c));
+            for (key, name) in [("card", card), ("retype", step.retype.as_ref())] {
+                if let Some(name) = name.filter(|n| !cards.contains(n)) {
+                    errors.push(
+                        LoadError::new(&format!("Unknown card type \"{}\"", name))
+                            .path(&format!("Migrations[{}].{}", i, key))
+                            .hint(&format!("Pick one of Cards.types: {}", cards.join(", "))),
+                    );
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            let errors = errors
+                .into_iter()
+                .map(|error| {
+                    let position = error.path.as_deref().and_then(|path| toml_position(metadata, path));
+                    error.at(position)
+                })
+                .collect();
+            return Err(LoadErrors(errors));
+        }
+
+        let mut metadata = MetaData::new(cards, shapes, fields, themes, &theme)
+            .map_err(|e| LoadError::new(&e.to_string()))?;
+        metadata.set_schema(version, migrations.into_iter().map(|(_, step)| step).collect());
+
         Ok(Config {
-            metadata: MetaData::new(cards, shapes, fields, theme),
+            metadata,
+            path: None,
         })
     }
 
     pub fn get_metadata(&self) -> &MetaData {
         &self.metadata
     }
+
+    pub fn get_path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+}
+
+// A sub-table of `parent`, reporting it when missing or of another type
+fn table<'a>(parent: &'a toml::Table, key: &str, path: &str, errors: &mut Vec<LoadError>) -> Option<&'a toml::Table> {
+    match parent.get(key) {
+        Some(toml::Value::Table(table)) => Some(table),
+        Some(value) => {
+            errors.push(LoadError::new(&format!("Expected a table, found {}", value.type_str())).path(path));
+            None
+        }
+        None => {
+            errors.push(LoadError::new("Missing table").path(path).hint(&format!("Declare it as [{}]", path)));
+            None
+        }
+    }
+}
+
+// An array of strings, reporting the value or each element that is not one
+fn string_list(value: Option<&toml::Value>, path: &str, errors: &mut Vec<LoadError>) -> Vec<String> {
+    let values = match value {
+        Some(toml::Value::Array(values)) => values,
+        Some(value) => {
+            errors.push(LoadError::new(&format!("Expected an array of strings, found {}", value.type_str())).path(path));
+            return vec![];
+        }
+        None => {
+            errors.push(LoadError::new("Missing key").path(path));
+            return vec![];
+        }
+    };
+    let mut strings = vec![];
+    for (i, v) in values.iter().enumerate() {
+        match v.as_str() {
+            Some(s) => strings.push(s.to_string()),
+            None => errors.push(LoadError::new(&format!("Expected a string, found {}", v.type_str())).path(&format!("{}[{}]", path, i))),
+        }
+    }
+    strings
+}
+
+fn find_metadata(content_file: &str) -> Option<PathBuf> {
+    let mut candidates = vec![];
+
+    if let Some(dir) = Path::new(content_file).parent() {
+        candidates.push(dir.join(METADATA_FILE));
+    }
+    if let Ok(cwd) = env::current_dir() {
+        candidates.push(project_root(&cwd).join(METADATA_FILE));
+    }
+    if let Some(dir) = xdg_home("XDG_CONFIG_HOME", ".config") {
+        candidates.push(dir.join("obentou").join(METADATA_FILE));
+    }
+
+    candidates.into_iter().find(|path| path.is_file())
+}
+
+// The nearest ancestor holding a `.git` directory, or `dir` itself
+fn project_root(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .find(|d| d.join(".git").exists())
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+/// The XDG base directory named by `var`, or `fallback` under `$HOME` when it is unset.
+pub fn xdg_home(var: &str, fallback: &str) -> Option<PathBuf> {
+    match env::var_os(var) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)),
+    }
 }
 // fn main() -> Result<()> {
 // &    le.to_string()t config = Config::new()?;
@@ -1,19 +1,41 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom};
+use std::fs::File;
+use std::io::{self, Read, Write};
 
 use ratatui::style::Color;
-use serde_json::{Value,  json};
+use serde::Serialize;
+use serde_json::{Map, Value,  json};
 
 use anyhow::{anyhow, Context, Result};
 
+use crate::arrange::parse_shape;
+use crate::error::{LoadError, LoadErrors};
+use crate::field::{initial_fields, FieldSpec};
+use crate::format::Format;
+use crate::migrate::{Migration, FIRST_VERSION};
+use crate::jsonc::{insert_comments, scan, Comments};
+use crate::storage::write_atomic;
+
+/// Shape of every Section card, which is not stored in the file.
+pub const SECTION_SHAPE: &str = "1x8";
+
+/// Shape of cards without a "shape" key.
+pub const DEFAULT_SHAPE: &str = "2x2";
+
+// Profile fields used when metadata declares none
+const PROFILE_FIELDS: [&str; 3] = ["name", "avatar", "bio"];
+
 #[derive(Debug, Clone)]
 pub struct MetaData {
     cards: Vec<String>, // card types: Note, Photo, ...
     shapes: Vec<String>, // card shapes: 1x4, 2x4, 4x4, ...
-    fields: HashMap<String, Vec<String>>,
+    fields: HashMap<String, Vec<FieldSpec>>,
+    themes: BTreeMap<String, Vec<Color>>,
+    theme_name: String,
     theme: HashMap<String, Color>,
+    version: u64,
+    migrations: Vec<Migration>,
 }
 
 #[derive(Debug, Clone)]
@@ -21,34 +43,191 @@ pub struct Item {
     title: String,
     shape: String,
     lines: Vec<String>,
+    // Comments written above the card in JSONC files
+    comments: Vec<String>,
+}
+
+/// A single problem found while checking a card against its fields.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub field: Option<String>,
+    pub message: String,
+}
+
+/// What a content file looked like when loaded, so that saving it keeps
+/// unknown top-level keys, key order and formatting.
+#[derive(Debug, Clone)]
+pub struct Document {
+    root: Map<String, Value>,
+    text: String,
+    indent: Option<String>,
+    trailing_newline: bool,
+    format: Format,
+    header: Vec<String>,
+    // Schema version written on save, `None` to leave the file unmarked
+    version: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Data {
     pub metadata: MetaData,
     pub items: Vec<Item>,
+    pub document: Document,
 }
 
-pub fn parse_data_from_file(filename: &str) -> Result<Vec<Item>> {
+/// Path standing for stdin when reading and stdout when writing.
+pub const STDIO_PATH: &str = "-";
+
+pub fn parse_data_from_file(filename: &str) -> Result<(Vec<Item>, Document)> {
 
     // write_info!(format!("read file: {}", filename));
 
-    let mut file = File::open(filename)?;
+    let text = read_source(filename)?;
+    Ok(parse_data_from_str(&text, Format::from_path(filename)).map_err(|e| e.in_file(filename))?)
+}
+
+/// Parse a content document, reporting every structural problem at once.
+pub fn parse_data_from_str(data: &str, format: Format) -> Result<(Vec<Item>, Document), LoadErrors> {
+    // JSON may carry comments, which are kept with the cards they annotate
+    let (data_json, comments, indent, scanned) = match format {
+        Format::Json => {
+            let scanned = scan(data)?;
+            let value: Value = serde_json::from_str(&scanned.json).map_err(|e| LoadError::from_json(&e))?;
+            let indent = detect_indent(&scanned.json);
+            (value, scanned.comments.clone(), indent, Some(scanned))
+        }
+        _ => (format.parse(data)?, Comments::default(), None, None),
+    };
+
+    let locate = |card: Option<usize>| match &scanned {
+        Some(scanned) => scanned.position(data, card),
+        None => format.position(data, card),
+    };
+    let items = parse_data(&data_json, &comments, &locate)?;
+
+    let document = Document {
+        root: data_json.as_object().cloned().unwrap_or_default(),
+        text: data.to_string(),
+        indent,
+        trailing_newline: data.ends_with('\n'),
+        format,
+        header: comments.header.clone(),
+        version: data_json.get("version").and_then(Value::as_u64),
+    };
+
+    Ok((items, document))
+}
 
+/// Read a whole file, or stdin for `-`.
+pub fn read_source(filename: &str) -> Result<String> {
     let mut data = String::new();
-    file.read_to_string(&mut data)
-        .expect("Failed to read file");
+    if filename == STDIO_PATH {
+        io::stdin().read_to_string(&mut data).context("Failed to read stdin")?;
+    } else {
+        File::open(filename)
+            .and_then(|mut file| file.read_to_string(&mut data))
+            .with_context(|| format!("Failed to read file {}", filename))?;
+    }
+    Ok(data)
+}
+
+/// Save in the format given by the file extension.
+pub fn save_data_to_file(data: &Data, filename: &str) -> Result<()> {
+    save_data_as(data, filename, Format::from_path(filename))
+}
 
-    let data_json: Value = serde_json::from_str(&data)?;
+pub fn save_data_as(data: &Data, filename: &str, format: Format) -> Result<()> {
 
-    Ok(parse_data(&data_json))
+    let output = serialize_document(data, format)?;
+    write_source(filename, &output)
 }
 
-pub fn save_data_to_file(data: &Data, filename: &str) -> Result<()> {
+/// Write a whole file, or stdout for `-`.
+pub fn write_source(filename: &str, contents: &str) -> Result<()> {
+    if filename == STDIO_PATH {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(contents.as_bytes())?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    write_atomic(filename, contents.as_bytes())
+}
+
+/// Render `data` in canonical form for the format it was read in. JSON uses
+/// a two-space indent and no trailing newline.
+pub fn serialize_data(data: &Data) -> Result<String> {
+    let output = data.document.format.write(&build_document(data)?)?;
+    match data.document.format {
+        Format::Json => with_comments(output, data),
+        _ => Ok(output),
+    }
+}
+
+/// Render `data` the way its file was written, byte for byte if nothing changed.
+fn serialize_document(data: &Data, format: Format) -> Result<String> {
+    let document = &data.document;
+    let output_data = build_document(data)?;
+
+    // Converted documents are written from scratch in the new format
+    if document.format != format {
+        return format.write(&output_data);
+    }
+
+    if !document.text.is_empty() {
+        let original = write_styled(&Value::Object(document.root.clone()), document)?;
+        let current = write_styled(&output_data, document)?;
+        if original == current {
+            return Ok(document.text.clone());
+        }
+    }
+
+    match format {
+        Format::Json => with_comments(write_styled(&output_data, document)?, data),
+        _ => write_styled(&output_data, document),
+    }
+}
+
+fn with_comments(output: String, data: &Data) -> Result<String> {
+    let mut items = data.items.iter();
+    let comments = Comments {
+        header: data.document.header.clone(),
+        profile: items.next().map(|item| item.comments.clone()).unwrap_or_default(),
+        cards: items.map(|item| item.comments.clone()).collect(),
+    };
+    if comments.is_empty() {
+        return Ok(output);
+    }
+    insert_comments(&output, &comments)
+}
+
+// Only JSON keeps the indent and trailing newline of the original
+fn write_styled(value: &Value, document: &Document) -> Result<String> {
+    if document.format != Format::Json {
+        return document.format.write(value);
+    }
+    let mut output = match &document.indent {
+        Some(indent) => {
+            let mut buf = Vec::new();
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            let mut ser = serde_json::Serializer::with_formatter(&mut buf, formatter);
+            value.serialize(&mut ser)?;
+            String::from_utf8(buf)?
+        }
+        None => serde_json::to_string(value)?,
+    };
+    if document.trailing_newline {
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+// Profile and showcase are put back in their original place among any other keys
+fn build_document(data: &Data) -> Result<Value> {
 
     let mut showcase = json!([]);
 
-    let profile_str = data.items.get(0).context("No profile found")?.get_lines().join("\n");
+    let profile_str = data.items.first().context("No profile found")?.get_lines().join("\n");
     let profile: Value = serde_json::from_str(&profile_str)?;
 
     for i in 1..data.items.len() {
@@ -66,23 +245,34 @@ pub fn save_data_to_file(data: &Data, filename: &str) -> Result<()> {
         }
     }
 
-    let output_data = json!({
-        "profile": profile,
-        "showcase": showcase,
-    });
+    let mut output_data = data.document.root.clone();
+    output_data.insert("profile".to_string(), profile);
+    output_data.insert("showcase".to_string(), showcase);
 
-    // write_info!(format!("output: {}", output_data));
+    // A new version key goes first, an existing one is updated in place
+    if let Some(version) = data.document.version {
+        if output_data.contains_key("version") {
+            output_data.insert("version".to_string(), json!(version));
+        } else {
+            let mut versioned = Map::new();
+            versioned.insert("version".to_string(), json!(version));
+            versioned.extend(output_data);
+            output_data = versioned;
+        }
+    }
 
-    let mut file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(filename)?;
+    // write_info!(format!("output: {}", output_data));
 
-    file.seek(SeekFrom::Start(0))?;
-    serde_json::to_writer_pretty(&mut file, &output_data)?;
+    Ok(Value::Object(output_data))
+}
 
-    Ok(())
+// Indent of the first indented line, or None for single-line documents
+fn detect_indent(text: &str) -> Option<String> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    lines.next()?;
+    let line = lines.next()?;
+    let trimmed = line.trim_start();
+    Some(line[..line.len() - trimmed.len()].to_string())
 }
 
 pub fn format_json(input: &str) -> Vec<String> {
@@ -107,51 +297,149 @@ fn format_json_value(value: &Value) -> Vec<String> {
 }
 
 
-fn parse_data(json_data: &Value) -> Vec<Item> {
+// Sections always span the full width, other cards keep their shape under "shape"
+fn shape_of(card_type: &str, value: &Value) -> String {
+    if card_type == "Section" {
+        SECTION_SHAPE.to_string()
+    } else {
+        value.get("shape").and_then(Value::as_str).unwrap_or(DEFAULT_SHAPE).to_string()
+    }
+}
+
+// Where the profile (`None`) or a showcase card starts in the file, if known
+type Locate<'a> = &'a dyn Fn(Option<usize>) -> Option<(usize, usize)>;
+
+fn parse_data(json_data: &Value, comments: &Comments, locate: Locate) -> Result<Vec<Item>, LoadErrors> {
+
+    if !json_data.is_object() {
+        return Err(LoadError::new(&format!("The document must be an object, found {}", kind(json_data)))
+            .hint("A content file looks like { \"profile\": { ... }, \"showcase\": [ ... ] }")
+            .into());
+    }
+
+    let mut errors = vec![];
 
     let profile = &json_data["profile"];
-    let showcase = &json_data["showcase"];
+    match profile {
+        Value::Object(_) => {}
+        Value::Null => errors.push(
+            LoadError::new("Missing profile")
+                .path("profile")
+                .hint("Add \"profile\": {} next to \"showcase\""),
+        ),
+        _ => errors.push(
+            LoadError::new(&format!("The profile must be an object, found {}", kind(profile)))
+                .at(locate(None))
+                .path("profile"),
+        ),
+    }
 
-    let arr = showcase.as_array().unwrap();
+    let showcase = &json_data["showcase"];
+    let arr = match showcase {
+        Value::Array(arr) => arr.as_slice(),
+        Value::Null => {
+            errors.push(
+                LoadError::new("Missing showcase")
+                    .path("showcase")
+                    .hint("Add \"showcase\": [] for a page without cards"),
+            );
+            &[]
+        }
+        _ => {
+            errors.push(
+                LoadError::new(&format!("The showcase must be an array of cards, found {}", kind(showcase)))
+                    .path("showcase"),
+            );
+            &[]
+        }
+    };
 
     // .map(|v| serde_json::to_string(v).unwrap())
     //
     let mut items = vec![];
     items.push(Item {
         title: "Profile".to_string(),
-        lines: format_json_value(&profile),
+        lines: format_json_value(profile),
         shape: "4x4".to_string(),
+        comments: comments.profile.clone(),
     });
 
     // let mut cards = vec!["Profile".to_string()];
     // let mut contents = vec![format_json_value(&profile)];
 
     // Iterate over each object in the array
-    for obj in arr {
-        if let Value::Object(map) = obj {
-            // Iterate over key-value pairs in the object
-            for (key, value) in map {
-                // if let value_map = serde_json::from_str(value) {
-                let shape = if key != "Section"  {
-                    if let Some(shape) = value.get("shape").and_then(Value::as_str) {
-                        shape
-                    } else {
-                        "2x2"
-                    }
-                } else {
-                    "1x8"
-                };
-                items.push( Item {
-                    title: key.to_string(),
-                    lines: format_json_value(value),
-                    shape: shape.to_string(),
-                } );
-                // }
+    for (k, obj) in arr.iter().enumerate() {
+        let path = format!("showcase[{}]", k);
+        let map = match obj {
+            Value::Object(map) if !map.is_empty() => map,
+            Value::Object(_) => {
+                errors.push(
+                    LoadError::new("A card needs its type as key, found an empty object")
+                        .at(locate(Some(k)))
+                        .path(&path)
+                        .hint("Write cards as { \"Note\": { ... } }"),
+                );
+                continue;
+            }
+            _ => {
+                errors.push(
+                    LoadError::new(&format!("A card must be an object with its type as key, found {}", kind(obj)))
+                        .at(locate(Some(k)))
+                        .path(&path)
+                        .hint("Write cards as { \"Note\": { ... } }"),
+                );
+                continue;
+            }
+        };
+
+        let mut card_comments = comments.cards.get(k).cloned().unwrap_or_default();
+        // Iterate over key-value pairs in the object
+        for (key, value) in map {
+            let path = format!("{}.{}", path, key);
+            if !value.is_object() {
+                errors.push(
+                    LoadError::new(&format!("Card fields must be an object, found {}", kind(value)))
+                        .at(locate(Some(k)))
+                        .path(&path),
+                );
+                continue;
+            }
+            if let Some(shape) = value.get("shape") {
+                if shape.as_str().and_then(parse_shape).is_none() {
+                    errors.push(
+                        LoadError::new(&format!("Invalid shape {}", shape))
+                            .at(locate(Some(k)))
+                            .path(&format!("{}.shape", path))
+                            .hint("Shapes are rows x columns, like \"2x4\""),
+                    );
+                    continue;
+                }
             }
+            items.push( Item {
+                title: key.to_string(),
+                lines: format_json_value(value),
+                shape: shape_of(key, value),
+                comments: std::mem::take(&mut card_comments),
+            } );
         }
     }
 
-    items
+    if !errors.is_empty() {
+        return Err(LoadErrors(errors));
+    }
+    Ok(items)
+}
+
+// How a JSON value is described in error messages
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
 }
 
 
@@ -159,11 +447,37 @@ impl MetaData {
 
     pub fn new(cards: Vec<String>,
                shapes: Vec<String>,
-               fields: HashMap<String, Vec<String>>,
-               theme: HashMap<String, Color>) -> MetaData {
-        MetaData {
-            cards, shapes, fields, theme
-        }
+               mut fields: HashMap<String, Vec<FieldSpec>>,
+               themes: BTreeMap<String, Vec<Color>>,
+               theme_name: &str) -> Result<MetaData> {
+        // The profile is checked like any card, older metadata does not declare it
+        fields
+            .entry("Profile".to_string())
+            .or_insert_with(|| PROFILE_FIELDS.iter().map(|f| FieldSpec::named(f)).collect());
+        let mut metadata = MetaData {
+            cards, shapes, fields, themes,
+            theme_name: String::new(),
+            theme: HashMap::new(),
+            version: FIRST_VERSION,
+            migrations: vec![],
+        };
+        metadata.set_theme(theme_name)?;
+        Ok(metadata)
+    }
+
+    /// Schema version content files are expected to have, and the steps
+    /// that upgrade older files to it.
+    pub fn set_schema(&mut self, version: u64, migrations: Vec<Migration>) {
+        self.version = version;
+        self.migrations = migrations;
+    }
+
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn get_migrations(&self) -> &Vec<Migration> {
+        &self.migrations
     }
 
     pub fn get_cards(&self) -> &Vec<String> {
@@ -174,10 +488,6 @@ impl MetaData {
         &self.shapes
     }
 
-    pub fn get_fields(&self) -> &HashMap<String, Vec<String>> {
-        &self.fields
-    }
-
     pub fn get_card(&self, idx: usize) -> Option<&String> {
         self.cards.get(idx)
     }
@@ -186,31 +496,52 @@ impl MetaData {
         self.shapes.get(idx)
     }
 
-    pub fn get_field(&self, card_type: &str) -> Option<&Vec<String>> {
+    pub fn get_field(&self, card_type: &str) -> Option<&Vec<FieldSpec>> {
         self.fields.get(card_type)
     }
 
+    /// What a field of `card_type` is for, if metadata describes it.
+    pub fn field_help(&self, card_type: &str, field: &str) -> Option<String> {
+        self.get_field(card_type)?.iter().find(|f| f.name == field)?.help()
+    }
+
     pub fn count_shapes(&self) -> usize {
         self.shapes.len()
     }
 
-    pub fn count_cards(&self) -> usize {
-        self.cards.len()
+    pub fn index_of_shape(&self, shape: &str) -> usize {
+        self.find_shape(shape).unwrap_or_default()
     }
 
-    pub fn index_of_shape(&self, shape: &str) -> usize {
-        if let Some(index) = self.shapes.iter().position(|x| *x == shape) {
-            index
-        } else {
-            0
-        }
+    pub fn find_card(&self, card: &str) -> Option<usize> {
+        self.cards.iter().position(|x| *x == card)
+    }
+
+    pub fn find_shape(&self, shape: &str) -> Option<usize> {
+        self.shapes.iter().position(|x| *x == shape)
     }
 
+    pub fn get_theme_name(&self) -> &String {
+        &self.theme_name
+    }
 
-    pub fn get_theme(&self) -> &HashMap<String, Color> {
-        &self.theme
+    pub fn set_theme(&mut self, theme_name: &str) -> Result<()> {
+        let colors = self.themes.get(theme_name).context(format!("No such theme: {}", theme_name))?;
+        self.theme = self.cards.iter().cloned().zip(colors.iter().cloned()).collect();
+        self.theme_name = theme_name.to_string();
+        Ok(())
     }
 
+    pub fn next_theme(&mut self) -> Result<()> {
+        let next = self.themes
+            .keys()
+            .skip_while(|name| **name != self.theme_name)
+            .nth(1)
+            .or_else(|| self.themes.keys().next())
+            .context("No theme found")?
+            .clone();
+        self.set_theme(&next)
+    }
 
     pub fn get_card_color(&self, card: &str) -> &Color {
         if let Some(color) = self.theme.get(card) {
@@ -234,66 +565,202 @@ impl MetaData {
 
     pub fn create_item(&self, card_index: usize, shape_index: usize) -> Result<Item> {
 
-        let shape = if card_index == 0 {
-            "1x8"
-        } else {
-            self.get_shape(shape_index).context("Unexpected shape index")?
+        let card = self.get_card(card_index).context("Unexpected card index")?;
+        let fields = self.get_field(card).context(format!("No such field for card type '{}'", card))?;
+        let mut item = Item {
+            title: card.to_string(),
+            shape: SECTION_SHAPE.to_string(),
+            lines: format_json_value(&initial_fields(fields)),
+            comments: vec![],
         };
+        if card != "Section" {
+            item.set_shape(self.get_shape(shape_index).context("Unexpected shape index")?.to_string())?;
+        }
+        Ok(item)
+    }
 
-        let card = self.get_card(card_index).context("Unexpected card index")?;
-        let fields = &self.fields[card];
-        let mut strs = vec!["{".to_string()];
-        for (index, field) in fields.iter().enumerate() {
-            strs.push(format!("    \"{}\": \"\"{}",
-                              if field.ends_with('?') { field.strip_suffix("?").unwrap() } else { field },
-                              if index == fields.len() - 1 { "" } else { "," })
-            );
+    pub fn create_profile(&self) -> Item {
+        Item {
+            title: "Profile".to_string(),
+            shape: "4x4".to_string(),
+            lines: format_json_value(&initial_fields(self.get_field("Profile").map_or(&[], Vec::as_slice))),
+            comments: vec![],
         }
-        strs.push("}".to_string());
-        // for s in &strs {
-           // write_info!(s);
-        // }
-        Ok(Item {
-            title: card.to_string(),
-            shape: shape.to_string(),
-            lines: strs,
-        })
     }
 
     pub fn is_valid(&self, json_str: &str, card_type: &str) -> Result<()> {
-        match serde_json::from_str::<Value>(json_str) {
-            Ok(v) => {
-                let keys = self.get_field(card_type).context("No such field")?;
-                for key in keys {
-                    if !key.ends_with('?') {
-                        if let Some(_) = v.get(key) {
-                            continue;
-                        } else {
-                            return Err(anyhow!("Missing neccessary field!"));
-                        }
+        match self.find_problems(json_str, card_type).into_iter().next() {
+            Some(Problem { field: Some(field), message }) => Err(anyhow!("{}: {}", field, message)),
+            Some(problem) => Err(anyhow!(problem.message)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn find_problems(&self, json_str: &str, card_type: &str) -> Vec<Problem> {
+        let v = match serde_json::from_str::<Value>(json_str) {
+            Ok(v) => v,
+            Err(_) => return vec![Problem::new(None, "Invalid json format!")],
+        };
+        let keys = match self.get_field(card_type) {
+            Some(keys) => keys,
+            None => return vec![Problem::new(None, &format!("No such field for card type '{}'", card_type))],
+        };
+        if !v.is_object() {
+            return vec![Problem::new(None, "Card content is not a json object!")];
+        }
+
+        let mut problems = vec![];
+        for spec in keys {
+            match v.get(&spec.name) {
+                None if !spec.optional => problems.push(Problem::new(Some(&spec.name), "Missing neccessary field!")),
+                None => {}
+                Some(value) => {
+                    if let Some(reason) = spec.check(value) {
+                        problems.push(Problem::new(Some(&spec.name), &reason));
                     }
                 }
-                Ok(())
             }
-            Err(_) => Err(anyhow!("Invalid json format!"))
         }
+        if let Some(shape) = v.get("shape") {
+            let shaped = card_type != "Section" && card_type != "Profile";
+            if shaped && shape.as_str().is_none_or(|s| self.find_shape(s).is_none()) {
+                problems.push(Problem::new(Some("shape"), "Unknown shape!"));
+            }
+        }
+        problems
     }
 
 }
 
-impl Item {
+impl Problem {
+
+    fn new(field: Option<&str>, message: &str) -> Problem {
+        Problem {
+            field: field.map(|f| f.to_string()),
+            message: message.to_string(),
+        }
+    }
+
+}
+
+impl Default for Document {
+    fn default() -> Document {
+        Document {
+            root: Map::new(),
+            text: String::new(),
+            indent: Some("  ".to_string()),
+            trailing_newline: false,
+            format: Format::Json,
+            header: vec![],
+            version: None,
+        }
+    }
+}
+
+impl Data {
 
-    pub fn new(title: String,
-               shape: String,
-               lines: Vec<String>) -> Item {
+    /// A fresh document with an empty profile, optionally seeded with one card of each type.
+    pub fn create(metadata: MetaData, seed: bool) -> Result<Data> {
+        let mut items = vec![metadata.create_profile()];
+        if seed {
+            let shape_index = metadata.index_of_shape(DEFAULT_SHAPE);
+            for card_index in 0..metadata.get_cards().len() {
+                items.push(metadata.create_item(card_index, shape_index)?);
+            }
+        }
+        let document = Document {
+            version: Some(metadata.get_version()),
+            ..Document::default()
+        };
+        Ok(Data { metadata, items, document })
+    }
+
+    pub fn load(metadata: MetaData, filename: &str) -> Result<Data> {
+        let (items, document) = parse_data_from_file(filename)?;
+        Ok(Data { metadata, items, document })
+    }
+
+    /// Parse `text` read from `filename`, which picks the format and names
+    /// the file in errors.
+    pub fn parse(metadata: MetaData, text: &str, filename: &str) -> Result<Data> {
+        let (items, document) = parse_data_from_str(text, Format::from_path(filename))
+            .map_err(|e| e.in_file(filename))?;
+        Ok(Data { metadata, items, document })
+    }
+
+    /// Schema version of the document, files without one being the first.
+    pub fn get_version(&self) -> u64 {
+        self.document.version.unwrap_or(FIRST_VERSION)
+    }
+
+    pub fn set_version(&mut self, version: u64) {
+        self.document.version = Some(version);
+    }
+
+    pub fn check_item(&self, idx: usize) -> Result<()> {
+        let item = self.items.get(idx).context("No item found!")?;
+        self.metadata.is_valid(&item.get_lines().join("\n"), item.get_title())
+    }
+
+    pub fn insert_card(&mut self, idx: usize, card: &str, shape: &str) -> Result<usize> {
+        if idx == 0 || idx > self.items.len() {
+            return Err(anyhow!("Card index {} out of range", idx));
+        }
+        let card_index = self.metadata.find_card(card).context(format!("No such card type: {}", card))?;
+        let shape_index = self.metadata.find_shape(shape).context(format!("No such shape: {}", shape))?;
+
+        let new_item = self.metadata.create_item(card_index, shape_index)?;
+        self.items.insert(idx, new_item);
+        self.check_item(idx)?;
+        Ok(idx)
+    }
+
+    pub fn remove_card(&mut self, idx: usize) -> Result<Item> {
+        if idx == 0 || idx >= self.items.len() {
+            return Err(anyhow!("Card index {} out of range", idx));
+        }
+        Ok(self.items.remove(idx))
+    }
+
+    pub fn move_card(&mut self, from: usize, to: usize) -> Result<()> {
+        if from == 0 || from >= self.items.len() {
+            return Err(anyhow!("Card index {} out of range", from));
+        }
+        if to == 0 || to >= self.items.len() {
+            return Err(anyhow!("Card index {} out of range", to));
+        }
+        let item = self.items.remove(from);
+        self.items.insert(to, item);
+        Ok(())
+    }
 
-        Item { title, shape, lines }
+    pub fn reshape_card(&mut self, idx: usize, shape: &str) -> Result<()> {
+        if idx == 0 || idx >= self.items.len() {
+            return Err(anyhow!("Card index {} out of range", idx));
+        }
+        if self.metadata.find_shape(shape).is_none() {
+            return Err(anyhow!("No such shape: {}", shape));
+        }
+        let item = &mut self.items[idx];
+        if item.get_title() == "Section" {
+            return Err(anyhow!("Section cards cannot be reshaped"));
+        }
+        item.set_shape(shape.to_string())?;
+        self.check_item(idx)
     }
 
+}
+
+impl Item {
+
     pub fn get_title(&self) -> &String {
         &self.title
     }
 
+    pub fn set_title(&mut self, title: String) {
+        self.title = title;
+    }
+
     pub fn get_shape(&self) -> &String {
         &self.shape
     }
@@ -302,24 +769,49 @@ impl Item {
         &self.lines
     }
 
-    pub fn get_line(&self, idx: usize) -> Option<&String> {
-        self.lines.get(idx)
+    /// Change the shape of a card, writing it to the card's "shape" key.
+    /// Sections and the profile have a fixed shape and are left as they are.
+    pub fn set_shape(&mut self, shape: String) -> Result<()> {
+        if self.title == "Section" || self.title == "Profile" {
+            return Ok(());
+        }
+        self.set_field("shape", Value::String(shape.clone()))?;
+        self.shape = shape;
+        Ok(())
     }
 
-    pub fn set_title(&mut self, title: String) {
-        self.title = title;
+    pub fn set_lines_and_format(&mut self, lines: &[String]) {
+        self.lines = format_json(lines.join("\n").as_str());
+        if self.title != "Profile" {
+            if let Ok(value) = self.to_value() {
+                self.shape = shape_of(&self.title, &value);
+            }
+        }
     }
 
-    pub fn set_shape(&mut self, shape: String) {
-        self.shape = shape;
+    pub fn to_value(&self) -> Result<Value> {
+        Ok(serde_json::from_str(&self.lines.join("\n"))?)
     }
 
-    pub fn set_lines(&mut self, lines: &Vec<String>) {
-        self.lines = lines.to_vec();
+    // First non-empty text field, used to tell cards of the same type apart
+    pub fn label(&self) -> Option<String> {
+        let value = self.to_value().ok()?;
+        value
+            .as_object()?
+            .values()
+            .filter_map(Value::as_str)
+            .find(|text| !text.is_empty())
+            .map(str::to_string)
     }
 
-    pub fn set_lines_and_format(&mut self, lines: &[String]) {
-        self.lines = format_json(lines.join("\n").as_str());
+    pub fn set_field(&mut self, field: &str, value: Value) -> Result<()> {
+        let mut content = self.to_value()?;
+        content
+            .as_object_mut()
+            .context("Card content is not a json object!")?
+            .insert(field.to_string(), value);
+        self.lines = format_json_value(&content);
+        Ok(())
     }
 
 }
@@ -337,3 +829,103 @@ impl fmt::Display for Data {
     }
 }
 */
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    /// A document with an empty profile and `cards` as its showcase.
+    pub fn showcase(cards: &[String]) -> Data {
+        let text = format!(r#"{{"profile": {{}}, "showcase": [{}]}}"#, cards.join(","));
+        Data::parse(metadata(), &text, "test.json").unwrap()
+    }
+
+    pub fn note(title: &str, content: &str) -> String {
+        format!(r#"{{"Note": {{"title": "{}", "content": "{}"}}}}"#, title, content)
+    }
+
+    fn metadata() -> MetaData {
+        Config::parse(include_str!("../metadata.toml")).unwrap().get_metadata().clone()
+    }
+
+    const HAND_WRITTEN: &str = r#"{
+    "$schema": "./obentou.schema.json",
+    "showcase": [
+        {
+            "Note": {
+                "title": "Hello",
+                "pinned": true,
+                "content": "World"
+            }
+        }
+    ],
+    "profile": {
+        "name": "Ann",
+        "pronouns": "they/them",
+        "avatar": "",
+        "bio": ""
+    }
+}
+"#;
+
+    #[test]
+    fn saving_without_edits_is_byte_identical() {
+        let data = Data::parse(metadata(), HAND_WRITTEN, "test.json").unwrap();
+        assert_eq!(serialize_document(&data, Format::Json).unwrap(), HAND_WRITTEN);
+    }
+
+    #[test]
+    fn unknown_keys_and_key_order_survive_an_edit() {
+        let mut data = Data::parse(metadata(), HAND_WRITTEN, "test.json").unwrap();
+        let lines: Vec<String> = data.items[1].get_lines().iter().map(|l| l.replace("World", "There")).collect();
+        data.items[1].set_lines_and_format(&lines);
+        assert_eq!(serialize_document(&data, Format::Json).unwrap(), HAND_WRITTEN.replace("World", "There"));
+    }
+
+    const JSONC: &str = "// My page
+{
+  // Shown at the top
+  profile: {name: 'Ann', avatar: '', bio: ''},
+  showcase: [
+    // The greeting
+    {'Note': {title: 'Hello', content: 'World', shape: '2x2',},},
+  ],
+}
+";
+
+    #[test]
+    fn saving_jsonc_without_edits_is_byte_identical() {
+        let data = Data::parse(metadata(), JSONC, "test.json").unwrap();
+        assert_eq!(serialize_document(&data, Format::Json).unwrap(), JSONC);
+    }
+
+    #[test]
+    fn jsonc_comments_survive_an_edit() {
+        let mut data = Data::parse(metadata(), JSONC, "test.json").unwrap();
+        let lines: Vec<String> = data.items[1].get_lines().iter().map(|l| l.replace("World", "There")).collect();
+        data.items[1].set_lines_and_format(&lines);
+        // Comments stay above what they belong to, the rest becomes strict JSON
+        let expected = r#"// My page
+{
+  // Shown at the top
+  "profile": {
+    "name": "Ann",
+    "avatar": "",
+    "bio": ""
+  },
+  "showcase": [
+    // The greeting
+    {
+      "Note": {
+        "title": "Hello",
+        "content": "There",
+        "shape": "2x2"
+      }
+    }
+  ]
+}
+"#;
+        assert_eq!(serialize_document(&data, Format::Json).unwrap(), expected);
+    }
+}
@@ -0,0 +1,363 @@
+use crate::arrange::{arrange_grid, CellSize, GRID_SIZE};
+use crate::data::{Data, Item};
+
+use serde_json::{Map, Value};
+
+use std::fmt;
+
+/// A card-level change between two versions of a content file.
+///
+/// Indices are item indices as shown by `list`, with 0 being the profile.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Added { index: usize, card_type: String, shape: String },
+    Removed { index: usize, card_type: String, shape: String },
+    Moved { from: usize, to: usize, card_type: String },
+    Reshaped { index: usize, card_type: String, from: String, to: String },
+    Field { index: usize, card_type: String, field: String, old: Option<Value>, new: Option<Value> },
+}
+
+/// Cells that changed position or size in the arranged grid.
+#[derive(Debug, Clone)]
+pub struct CellMove {
+    pub from: usize,
+    pub to: usize,
+    pub card_type: String,
+    pub old_cell: Option<CellSize>,
+    pub new_cell: Option<CellSize>,
+}
+
+/// Compare two documents card by card.
+pub fn diff_data(old: &Data, new: &Data) -> Vec<Change> {
+    diff_matched(old, new, &match_cards(old, new))
+}
+
+/// Compare two documents whose matching cards, as `(old, new)` indices, are
+/// already known.
+pub fn diff_matched(old: &Data, new: &Data, pairs: &[(usize, usize)]) -> Vec<Change> {
+    let mut changes = vec![];
+
+    if let (Some(old_profile), Some(new_profile)) = (old.items.first(), new.items.first()) {
+        diff_fields(0, old_profile, new_profile, &mut changes);
+    }
+
+    for i in 1..old.items.len() {
+        if !pairs.iter().any(|(from, _)| *from == i) {
+            let item = &old.items[i];
+            changes.push(Change::Removed {
+                index: i,
+                card_type: item.get_title().to_string(),
+                shape: item.get_shape().to_string(),
+            });
+        }
+    }
+    for j in 1..new.items.len() {
+        if !pairs.iter().any(|(_, to)| *to == j) {
+            let item = &new.items[j];
+            changes.push(Change::Added {
+                index: j,
+                card_type: item.get_title().to_string(),
+                shape: item.get_shape().to_string(),
+            });
+        }
+    }
+
+    let in_order = longest_ordered_pairs(pairs);
+    for (i, j) in pairs {
+        let (old_item, new_item) = (&old.items[*i], &new.items[*j]);
+        if !in_order.contains(&(*i, *j)) {
+            changes.push(Change::Moved {
+                from: *i,
+                to: *j,
+                card_type: new_item.get_title().to_string(),
+            });
+        }
+        if old_item.get_shape() != new_item.get_shape() {
+            changes.push(Change::Reshaped {
+                index: *j,
+                card_type: new_item.get_title().to_string(),
+                from: old_item.get_shape().to_string(),
+                to: new_item.get_shape().to_string(),
+            });
+        }
+        diff_fields(*j, old_item, new_item, &mut changes);
+    }
+
+    changes
+}
+
+/// Compare where matched cards end up in the arranged grid.
+pub fn diff_layout(old: &Data, new: &Data) -> Vec<CellMove> {
+    let old_cells = arrange_grid(GRID_SIZE, &card_shapes(old));
+    let new_cells = arrange_grid(GRID_SIZE, &card_shapes(new));
+    let find = |cells: &[CellSize], index: usize| {
+        cells.iter().find(|c| c.get_card_index() + 1 == index).cloned()
+    };
+
+    let mut moves = vec![];
+    for (i, j) in match_cards(old, new) {
+        let old_cell = find(&old_cells, i);
+        let new_cell = find(&new_cells, j);
+        let same = match (&old_cell, &new_cell) {
+            (Some(a), Some(b)) => {
+                (a.get_start_row(), a.get_start_col(), a.get_width(), a.get_height())
+                    == (b.get_start_row(), b.get_start_col(), b.get_width(), b.get_height())
+            }
+            (None, None) => true,
+            _ => false,
+        };
+        if !same {
+            moves.push(CellMove {
+                from: i,
+                to: j,
+                card_type: new.items[j].get_title().to_string(),
+                old_cell,
+                new_cell,
+            });
+        }
+    }
+    moves.sort_by_key(|m| m.to);
+    moves
+}
+
+/// Pair up showcase cards of `old` and `new` that are the same card.
+///
+/// Identical cards are matched first, then cards of the same type that share
+/// the most field values, preferring nearby positions.
+pub fn match_cards(old: &Data, new: &Data) -> Vec<(usize, usize)> {
+    let old_fields: Vec<Map<String, Value>> = old.items.iter().map(card_fields).collect();
+    let new_fields: Vec<Map<String, Value>> = new.items.iter().map(card_fields).collect();
+
+    let mut pairs = vec![];
+    let mut used = vec![false; new.items.len()];
+
+    for (i, fields) in old_fields.iter().enumerate().skip(1) {
+        let found = (1..new.items.len()).find(|&j| {
+            !used[j]
+                && old.items[i].get_title() == new.items[j].get_title()
+                && *fields == new_fields[j]
+        });
+        if let Some(j) = found {
+            used[j] = true;
+            pairs.push((i, j));
+        }
+    }
+
+    loop {
+        let mut best: Option<(usize, usize, usize, usize)> = None;
+        for (i, fields) in old_fields.iter().enumerate().skip(1) {
+            if pairs.iter().any(|(from, _)| *from == i) {
+                continue;
+            }
+            for j in 1..new.items.len() {
+                if used[j] || old.items[i].get_title() != new.items[j].get_title() {
+                    continue;
+                }
+                let score = shared_fields(fields, &new_fields[j]);
+                let distance = i.abs_diff(j);
+                let better = match best {
+                    Some((_, _, s, d)) => score > s || (score == s && distance < d),
+                    None => score > 0,
+                };
+                if better {
+                    best = Some((i, j, score, distance));
+                }
+            }
+        }
+        match best {
+            Some((i, j, _, _)) => {
+                used[j] = true;
+                pairs.push((i, j));
+            }
+            None => break,
+        }
+    }
+
+    pairs.sort();
+    pairs
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { index, card_type, shape } => {
+                write!(f, "added     {}. {}-{}", index, card_type, shape)
+            }
+            Change::Removed { index, card_type, shape } => {
+                write!(f, "removed   {}. {}-{}", index, card_type, shape)
+            }
+            Change::Moved { from, to, card_type } => {
+                write!(f, "moved     {}. {} -> {}", from, card_type, to)
+            }
+            Change::Reshaped { index, card_type, from, to } => {
+                write!(f, "reshaped  {}. {} {} -> {}", index, card_type, from, to)
+            }
+            Change::Field { index, card_type, field, old, new } => {
+                let show = |v: &Option<Value>| match v {
+                    Some(v) => v.to_string(),
+                    None => "(none)".to_string(),
+                };
+                write!(f, "changed   {}. {} {}: {} -> {}", index, card_type, field, show(old), show(new))
+            }
+        }
+    }
+}
+
+impl fmt::Display for CellMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |c: &Option<CellSize>| match c {
+            Some(c) => format!(
+                "row {} col {} ({}x{})",
+                c.get_start_row(),
+                c.get_start_col(),
+                c.get_height(),
+                c.get_width()
+            ),
+            None => "unplaced".to_string(),
+        };
+        let index = if self.from == self.to {
+            self.to.to_string()
+        } else {
+            format!("{}->{}", self.from, self.to)
+        };
+        write!(f, "cell      {}. {} {} -> {}", index, self.card_type, show(&self.old_cell), show(&self.new_cell))
+    }
+}
+
+fn diff_fields(index: usize, old: &Item, new: &Item, changes: &mut Vec<Change>) {
+    let old_fields = card_fields(old);
+    let new_fields = card_fields(new);
+
+    for (key, old_value) in &old_fields {
+        let new_value = new_fields.get(key);
+        if new_value != Some(old_value) {
+            changes.push(Change::Field {
+                index,
+                card_type: new.get_title().to_string(),
+                field: key.to_string(),
+                old: Some(old_value.clone()),
+                new: new_value.cloned(),
+            });
+        }
+    }
+    for (key, new_value) in &new_fields {
+        if !old_fields.contains_key(key) {
+            changes.push(Change::Field {
+                index,
+                card_type: new.get_title().to_string(),
+                field: key.to_string(),
+                old: None,
+                new: Some(new_value.clone()),
+            });
+        }
+    }
+}
+
+// Card fields without the shape, which is compared on its own
+fn card_fields(item: &Item) -> Map<String, Value> {
+    let mut fields = match item.to_value() {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    fields.remove("shape");
+    fields
+}
+
+fn shared_fields(a: &Map<String, Value>, b: &Map<String, Value>) -> usize {
+    a.iter().filter(|(k, v)| b.get(*k) == Some(*v)).count()
+}
+
+fn card_shapes(data: &Data) -> Vec<String> {
+    data.items
+        .iter()
+        .skip(1)
+        .map(|item| format!("{}-{}", item.get_title(), item.get_shape()))
+        .collect()
+}
+
+// Pairs (sorted by old index) that keep their relative order; the rest moved
+fn longest_ordered_pairs(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let n = pairs.len();
+    let mut length = vec![1; n];
+    let mut prev = vec![None; n];
+    for i in 0..n {
+        for j in 0..i {
+            if pairs[j].1 < pairs[i].1 && length[j] + 1 > length[i] {
+                length[i] = length[j] + 1;
+                prev[i] = Some(j);
+            }
+        }
+    }
+
+    let mut ordered = vec![];
+    let mut cursor = (0..n).max_by_key(|&i| length[i]);
+    while let Some(i) = cursor {
+        ordered.push(pairs[i]);
+        cursor = prev[i];
+    }
+    ordered.reverse();
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::tests::{note, showcase};
+
+    #[test]
+    fn ordered_pairs_keep_everything_in_order() {
+        let pairs = [(1, 1), (2, 2), (3, 3)];
+        assert_eq!(longest_ordered_pairs(&pairs), pairs);
+        assert_eq!(longest_ordered_pairs(&[]), vec![]);
+    }
+
+    #[test]
+    fn ordered_pairs_leave_out_the_moved_card() {
+        // The last card moved to the front
+        assert_eq!(longest_ordered_pairs(&[(1, 2), (2, 3), (3, 1)]), vec![(1, 2), (2, 3)]);
+        // The first card moved to the end
+        assert_eq!(longest_ordered_pairs(&[(1, 3), (2, 1), (3, 2)]), vec![(2, 1), (3, 2)]);
+    }
+
+    #[test]
+    fn identical_cards_are_matched_across_moves() {
+        let old = showcase(&[note("a", "1"), note("b", "2"), note("c", "3")]);
+        let new = showcase(&[note("c", "3"), note("a", "1"), note("b", "2")]);
+        assert_eq!(match_cards(&old, &new), vec![(1, 2), (2, 3), (3, 1)]);
+    }
+
+    #[test]
+    fn edited_cards_are_matched_by_shared_fields() {
+        let old = showcase(&[note("a", "1"), note("b", "2")]);
+        let new = showcase(&[note("b", "changed"), note("a", "1")]);
+        assert_eq!(match_cards(&old, &new), vec![(1, 2), (2, 1)]);
+    }
+
+    #[test]
+    fn unrelated_cards_are_not_matched() {
+        let old = showcase(&[note("a", "1")]);
+        let new = showcase(&[note("b", "2"), r#"{"Section": {"title": "a"}}"#.to_string()]);
+        assert_eq!(match_cards(&old, &new), vec![]);
+    }
+
+    #[test]
+    fn equally_similar_cards_match_the_nearest() {
+        let old = showcase(&[note("x", "1"), note("x", "2"), note("x", "3")]);
+        let new = showcase(&[note("x", "4"), note("x", "5"), note("x", "6")]);
+        assert_eq!(match_cards(&old, &new), vec![(1, 1), (2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn moved_card_is_reported_once() {
+        let old = showcase(&[note("a", "1"), note("b", "2"), note("c", "3")]);
+        let new = showcase(&[note("c", "3"), note("a", "1"), note("b", "2")]);
+        let moves: Vec<_> = diff_data(&old, &new)
+            .into_iter()
+            .filter_map(|c| match c {
+                Change::Moved { from, to, .. } => Some((from, to)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(moves, vec![(3, 1)]);
+    }
+}
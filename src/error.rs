@@ -0,0 +1,213 @@
+use std::error::Error;
+use std::fmt;
+
+/// A problem that stops a content or metadata file from loading.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadError {
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// Key path of the offending value, like `showcase[2].Photo`
+    pub path: Option<String>,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+/// Every problem found while loading a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadErrors(pub Vec<LoadError>);
+
+impl LoadError {
+
+    pub fn new(message: &str) -> LoadError {
+        LoadError {
+            file: None,
+            line: None,
+            column: None,
+            path: None,
+            message: message.to_string(),
+            hint: None,
+        }
+    }
+
+    pub fn at(mut self, position: Option<(usize, usize)>) -> LoadError {
+        if let Some((line, column)) = position {
+            self.line = Some(line);
+            self.column = Some(column);
+        }
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> LoadError {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn hint(mut self, hint: &str) -> LoadError {
+        self.hint = Some(hint.to_string());
+        self
+    }
+
+    /// The error as `validate --format json` reports it, with the key path
+    /// turned into a JSON pointer such as `/showcase/3/Photo/shape`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let pointer = self.path.as_deref().map(|path| {
+            path_segments(path).iter().map(|s| format!("/{}", s.replace('~', "~0").replace('/', "~1"))).collect::<String>()
+        });
+        serde_json::json!({
+            "file": self.file,
+            "line": self.line,
+            "column": self.column,
+            "path": pointer,
+            "message": self.message,
+            "hint": self.hint,
+        })
+    }
+
+    /// Syntax error reported by serde_json, with its position.
+    pub fn from_json(e: &serde_json::Error) -> LoadError {
+        let position = (e.line() > 0).then(|| (e.line(), e.column()));
+        let message = strip_position(&e.to_string(), position);
+        // Errors at the end of a line are reported at column 0
+        LoadError::new(&message).at(position.map(|(line, column)| (line, column.max(1))))
+    }
+
+    /// Syntax error reported by serde_yaml, with its position.
+    pub fn from_yaml(e: &serde_yaml::Error) -> LoadError {
+        let position = e.location().map(|l| (l.line(), l.column()));
+        LoadError::new(&strip_position(&e.to_string(), position)).at(position)
+    }
+
+    /// Syntax error reported by toml, with the position of its span in `text`.
+    pub fn from_toml(e: &toml::de::Error, text: &str) -> LoadError {
+        let position = e.span().map(|span| line_column(text, span.start));
+        let message: Vec<&str> = e.message().lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+        LoadError::new(&message.join(", ")).at(position)
+    }
+
+}
+
+impl LoadErrors {
+
+    /// Name the file every error comes from; `-` is shown as `<stdin>`.
+    pub fn in_file(mut self, filename: &str) -> LoadErrors {
+        let name = if filename == "-" { "<stdin>" } else { filename };
+        for error in &mut self.0 {
+            error.file = Some(name.to_string());
+        }
+        self
+    }
+
+    /// Line and column of the first error that has one.
+    pub fn first_position(&self) -> Option<(usize, usize)> {
+        self.0.iter().find_map(|e| Some((e.line?, e.column.unwrap_or(1))))
+    }
+
+}
+
+impl From<LoadError> for LoadErrors {
+    fn from(error: LoadError) -> LoadErrors {
+        LoadErrors(vec![error])
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut location = vec![];
+        if let Some(file) = &self.file {
+            location.push(file.to_string());
+        }
+        if let Some(line) = self.line {
+            location.push(line.to_string());
+            if let Some(column) = self.column {
+                location.push(column.to_string());
+            }
+        }
+        if !location.is_empty() {
+            write!(f, "{}: ", location.join(":"))?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "{}: ", path)?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n  hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for LoadErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self.0.iter().map(LoadError::to_string).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl Error for LoadError {}
+
+impl Error for LoadErrors {}
+
+/// 1-based line and column of a byte offset in `text`.
+pub fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |p| p + 1);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+/// Keys and indices of a key path like `showcase[3].Photo`.
+pub fn path_segments(path: &str) -> Vec<String> {
+    path.split('.')
+        .flat_map(|part| part.split('[').map(|s| s.trim_end_matches(']').to_string()))
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Line and column of the value at key `path` in a TOML document, or of its
+/// closest parent that can be found.
+pub fn toml_position(text: &str, path: &str) -> Option<(usize, usize)> {
+    let document = toml_edit::ImDocument::parse(text).ok()?;
+    let mut item = document.as_item();
+    let mut span = None;
+    for segment in path_segments(path) {
+        let next = match segment.parse::<usize>() {
+            Ok(i) if item.is_array() || item.is_array_of_tables() => item.get(i),
+            _ => item.get(segment.as_str()),
+        };
+        let Some(next) = next else { break };
+        item = next;
+        span = item.span().or(span);
+    }
+    span.map(|span| line_column(text, span.start))
+}
+
+// serde errors say " at line X column Y", which is shown separately
+fn strip_position(message: &str, position: Option<(usize, usize)>) -> String {
+    match position {
+        Some((line, column)) => message.replacen(&format!(" at line {} column {}", line, column), "", 1),
+        None => message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_paths_are_pointers() {
+        let error = LoadError::new("Invalid shape").path("showcase[3].Photo.shape").at(Some((7, 5)));
+        assert_eq!(error.to_json()["path"], "/showcase/3/Photo/shape");
+        assert_eq!(error.to_json()["line"], 7);
+        assert_eq!(LoadError::new("x").path("Themes.a/b").to_json()["path"], "/Themes/a~1b");
+        assert!(LoadError::new("x").to_json()["path"].is_null());
+    }
+
+    #[test]
+    fn toml_paths_are_located() {
+        let text = "[Cards]\ntypes = [\"Note\", 3]\n\n[[Migrations]]\nversion = 2\n\n[[Migrations]]\nversion = 3\ncard = \"Sticker\"\n";
+        assert_eq!(toml_position(text, "Cards.types[1]"), Some((2, 18)));
+        assert_eq!(toml_position(text, "Migrations[1].card"), Some((9, 8)));
+        assert_eq!(toml_position(text, "Migrations[1].retype"), Some((7, 1)));
+        assert_eq!(toml_position(text, "Themes"), None);
+    }
+}
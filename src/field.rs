@@ -0,0 +1,397 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+// CSS named colors, plus transparent and currentcolor
+const COLOR_NAMES: &[&str] = &[
+    "aliceblue", "antiquewhite", "aqua", "aquamarine", "azure", "beige", "bisque", "black",
+    "blanchedalmond", "blue", "blueviolet", "brown", "burlywood", "cadetblue", "chartreuse",
+    "chocolate", "coral", "cornflowerblue", "cornsilk", "crimson", "currentcolor", "cyan",
+    "darkblue", "darkcyan", "darkgoldenrod", "darkgray", "darkgreen", "darkgrey", "darkkhaki",
+    "darkmagenta", "darkolivegreen", "darkorange", "darkorchid", "darkred", "darksalmon",
+    "darkseagreen", "darkslateblue", "darkslategray", "darkslategrey", "darkturquoise",
+    "darkviolet", "deeppink", "deepskyblue", "dimgray", "dimgrey", "dodgerblue", "firebrick",
+    "floralwhite", "forestgreen", "fuchsia", "gainsboro", "ghostwhite", "gold", "goldenrod", "gray",
+    "green", "greenyellow", "grey", "honeydew", "hotpink", "indianred", "indigo", "ivory", "khaki",
+    "lavender", "lavenderblush", "lawngreen", "lemonchiffon", "lightblue", "lightcoral",
+    "lightcyan", "lightgoldenrodyellow", "lightgray", "lightgreen", "lightgrey", "lightpink",
+    "lightsalmon", "lightseagreen", "lightskyblue", "lightslategray", "lightslategrey",
+    "lightsteelblue", "lightyellow", "lime", "limegreen", "linen", "magenta", "maroon",
+    "mediumaquamarine", "mediumblue", "mediumorchid", "mediumpurple", "mediumseagreen",
+    "mediumslateblue", "mediumspringgreen", "mediumturquoise", "mediumvioletred", "midnightblue",
+    "mintcream", "mistyrose", "moccasin", "navajowhite", "navy", "oldlace", "olive", "olivedrab",
+    "orange", "orangered", "orchid", "palegoldenrod", "palegreen", "paleturquoise", "palevioletred",
+    "papayawhip", "peachpuff", "peru", "pink", "plum", "powderblue", "purple", "rebeccapurple",
+    "red", "rosybrown", "royalblue", "saddlebrown", "salmon", "sandybrown", "seagreen", "seashell",
+    "sienna", "silver", "skyblue", "slateblue", "slategray", "slategrey", "snow", "springgreen",
+    "steelblue", "tan", "teal", "thistle", "tomato", "transparent", "turquoise", "violet", "wheat",
+    "white", "whitesmoke", "yellow", "yellowgreen",
+];
+
+/// Value type of a field declared in `[Cards.Fields]`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    /// Anything, as for fields declared by name only
+    #[default]
+    Any,
+    String,
+    Url,
+    Color,
+    Datetime,
+    Integer,
+    Enum,
+    #[serde(rename = "string[]")]
+    Strings,
+    #[serde(rename = "object[]")]
+    Objects,
+}
+
+/// One field of a card type. Declared either by name, with a trailing `?`
+/// for optional fields, or as an inline table such as
+/// `{ name = "zoom", type = "integer", min = 1, max = 20 }`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSpec {
+    pub name: String,
+    pub optional: bool,
+    pub kind: FieldType,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    /// Most characters in a string, or entries in an array
+    pub max_len: Option<usize>,
+    /// Allowed values of an enum
+    pub values: Vec<String>,
+    /// Value of the field in new cards, instead of the empty one
+    pub default: Option<Value>,
+    /// Example value, shown with the description while editing
+    pub placeholder: Option<String>,
+    /// One line about what the field is for
+    pub description: Option<String>,
+}
+
+// The inline table form of a field in metadata.toml
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldDecl {
+    name: String,
+    #[serde(rename = "type", default)]
+    kind: FieldType,
+    min: Option<i64>,
+    max: Option<i64>,
+    max_len: Option<usize>,
+    #[serde(default)]
+    values: Vec<String>,
+    default: Option<Value>,
+    placeholder: Option<String>,
+    description: Option<String>,
+}
+
+impl FieldSpec {
+
+    /// A field declared by name only, which may hold any value.
+    pub fn named(name: &str) -> FieldSpec {
+        let (name, optional) = match name.strip_suffix('?') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        FieldSpec {
+            name: name.to_string(),
+            optional,
+            kind: FieldType::Any,
+            min: None,
+            max: None,
+            max_len: None,
+            values: vec![],
+            default: None,
+            placeholder: None,
+            description: None,
+        }
+    }
+
+    /// Read a declaration, a name or an inline table. Returns why it is
+    /// not one when it cannot be used.
+    pub fn from_toml(value: &toml::Value) -> Result<FieldSpec, String> {
+        let decl: FieldDecl = match value {
+            toml::Value::String(name) => return Ok(FieldSpec::named(name)),
+            toml::Value::Table(_) => value.clone().try_into().map_err(|e: toml::de::Error| e.message().trim().to_string())?,
+            _ => return Err(format!("Expected a field name or table, found {}", value.type_str())),
+        };
+
+        let spec = FieldSpec {
+            kind: decl.kind,
+            min: decl.min,
+            max: decl.max,
+            max_len: decl.max_len,
+            values: decl.values,
+            default: decl.default,
+            placeholder: decl.placeholder,
+            description: decl.description,
+            ..FieldSpec::named(&decl.name)
+        };
+        if spec.kind == FieldType::Enum && spec.values.is_empty() {
+            return Err(format!("Enum field '{}' needs a list of values", spec.name));
+        }
+        if let (Some(min), Some(max)) = (spec.min, spec.max) {
+            if min > max {
+                return Err(format!("Field '{}' has min {} above max {}", spec.name, min, max));
+            }
+        }
+        if let Some(default) = &spec.default {
+            if let Some(reason) = spec.check(default) {
+                return Err(format!("Default of field '{}' {}", spec.name, reason));
+            }
+        }
+        Ok(spec)
+    }
+
+    /// Why `value` does not fit this field, `None` when it does. Empty
+    /// strings are accepted for text fields that are not filled in yet.
+    pub fn check(&self, value: &Value) -> Option<String> {
+        if value.is_null() && self.optional {
+            return None;
+        }
+        match self.kind {
+            FieldType::Any => None,
+            FieldType::String => match value.as_str() {
+                Some(s) => self.check_len(s.chars().count(), "characters"),
+                None => Some("must be a string".to_string()),
+            },
+            FieldType::Url => match value.as_str() {
+                Some(s) if s.is_empty() || is_url(s) => self.check_len(s.chars().count(), "characters"),
+                _ => Some("must be a URL starting with http:// or https://".to_string()),
+            },
+            FieldType::Color => match value.as_str() {
+                Some(s) if s.is_empty() || is_color(s) => None,
+                _ => Some("must be a color such as \"#1e90ff\", \"rgb(30 144 255)\" or \"tomato\"".to_string()),
+            },
+            FieldType::Datetime => match value.as_str() {
+                Some(s) if s.is_empty() || is_datetime(s) => None,
+                _ => Some("must be a date such as \"2024-05-01\" or \"2024-05-01T18:30\"".to_string()),
+            },
+            FieldType::Integer => match value.as_i64() {
+                Some(n) if self.min.is_none_or(|min| n >= min) && self.max.is_none_or(|max| n <= max) => None,
+                _ => Some(format!("must be an integer{}", self.range())),
+            },
+            FieldType::Enum => match value.as_str() {
+                Some(s) if self.values.iter().any(|v| v == s) => None,
+                _ => Some(format!("must be one of {}", self.values.join(", "))),
+            },
+            FieldType::Strings => match value.as_array() {
+                Some(items) if items.iter().all(Value::is_string) => self.check_len(items.len(), "entries"),
+                _ => Some("must be an array of strings".to_string()),
+            },
+            FieldType::Objects => match value.as_array() {
+                Some(items) if items.iter().all(Value::is_object) => self.check_len(items.len(), "entries"),
+                _ => Some("must be an array of objects".to_string()),
+            },
+        }
+    }
+
+    /// The value a new card starts with: the declared default, or else the
+    /// empty value of the field's type.
+    pub fn initial_value(&self) -> Value {
+        if let Some(default) = &self.default {
+            return default.clone();
+        }
+        match self.kind {
+            FieldType::Integer => {
+                // Zero, or the bound closest to it
+                let n = self.min.map_or(0, |min| min.max(0));
+                Value::from(self.max.map_or(n, |max| n.min(max)))
+            }
+            FieldType::Enum => Value::from(self.values.first().cloned().unwrap_or_default()),
+            FieldType::Strings | FieldType::Objects => Value::Array(vec![]),
+            _ => Value::from(""),
+        }
+    }
+
+    /// Description and example of the field, as shown while editing it.
+    pub fn help(&self) -> Option<String> {
+        match (&self.description, &self.placeholder) {
+            (Some(description), Some(placeholder)) => Some(format!("{}: {} (e.g. {})", self.name, description, placeholder)),
+            (Some(description), None) => Some(format!("{}: {}", self.name, description)),
+            (None, Some(placeholder)) => Some(format!("{}: e.g. {}", self.name, placeholder)),
+            (None, None) => None,
+        }
+    }
+
+    fn check_len(&self, len: usize, unit: &str) -> Option<String> {
+        match self.max_len {
+            Some(max_len) if len > max_len => Some(format!("must have at most {} {}, found {}", max_len, unit, len)),
+            _ => None,
+        }
+    }
+
+    fn range(&self) -> String {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => format!(" from {} to {}", min, max),
+            (Some(min), None) => format!(" of at least {}", min),
+            (None, Some(max)) => format!(" of at most {}", max),
+            (None, None) => String::new(),
+        }
+    }
+
+}
+
+/// A new card's fields, each with its initial value.
+pub fn initial_fields(fields: &[FieldSpec]) -> Value {
+    let map: Map<String, Value> = fields.iter().map(|f| (f.name.clone(), f.initial_value())).collect();
+    Value::Object(map)
+}
+
+/// The key on a line of pretty-printed JSON, such as `zoom` in `"zoom": 12,`.
+pub fn key_on_line(line: &str) -> Option<String> {
+    let rest = line.trim_start().strip_prefix('"')?;
+    let end = rest.find('"')?;
+    rest[end + 1..].trim_start().starts_with(':').then(|| rest[..end].to_string())
+}
+
+fn is_url(s: &str) -> bool {
+    ["http://", "https://"]
+        .iter()
+        .any(|scheme| s.strip_prefix(scheme).is_some_and(|rest| !rest.is_empty() && !rest.contains(char::is_whitespace)))
+}
+
+// #rgb, #rgba, #rrggbb or #rrggbbaa, rgb()/rgba()/hsl()/hsla() or a CSS color name
+fn is_color(s: &str) -> bool {
+    if let Some(hex) = s.strip_prefix('#') {
+        return [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit());
+    }
+    let lower = s.to_ascii_lowercase();
+    if let Some((function, args)) = lower.strip_suffix(')').and_then(|f| f.split_once('(')) {
+        let args: Vec<&str> = args.split([',', ' ', '/']).filter(|a| !a.is_empty()).collect();
+        let number = |a: &str| {
+            let a = a.strip_suffix('%').or_else(|| a.strip_suffix("deg")).unwrap_or(a);
+            !a.is_empty() && a.parse::<f64>().is_ok_and(f64::is_finite)
+        };
+        return ["rgb", "rgba", "hsl", "hsla"].contains(&function)
+            && (args.len() == 3 || args.len() == 4)
+            && args.iter().all(|a| number(a));
+    }
+    COLOR_NAMES.binary_search(&lower.as_str()).is_ok()
+}
+
+// A date, optionally followed by a time and a UTC offset:
+// 2024-05-01, 2024-05-01T18:30, 2024-05-01 18:30:00Z, 2024-05-01T18:30+09:00
+fn is_datetime(s: &str) -> bool {
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+    let date: Vec<&str> = date.split('-').collect();
+    let date_ok = date.len() == 3
+        && number_in(date[0], 4, 0, 9999)
+        && number_in(date[1], 2, 1, 12)
+        && number_in(date[2], 2, 1, 31);
+
+    let time_ok = match time {
+        None => true,
+        Some(time) => {
+            let time = time.strip_suffix('Z').unwrap_or(time);
+            let time = match time.rfind(['+', '-']) {
+                Some(at) if is_clock(&time[at + 1..], false) => &time[..at],
+                _ => time,
+            };
+            is_clock(time, true)
+        }
+    };
+    date_ok && time_ok
+}
+
+// HH:MM, with optional :SS and fraction when `seconds` is allowed
+fn is_clock(s: &str, seconds: bool) -> bool {
+    let parts: Vec<&str> = s.split(':').collect();
+    let second_ok = |p: &str| {
+        let whole = p.split_once('.').map_or(p, |(whole, fraction)| {
+            if fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) { "" } else { whole }
+        });
+        number_in(whole, 2, 0, 60)
+    };
+    match parts.as_slice() {
+        [h, m] => number_in(h, 2, 0, 23) && number_in(m, 2, 0, 59),
+        [h, m, sec] if seconds => number_in(h, 2, 0, 23) && number_in(m, 2, 0, 59) && second_ok(sec),
+        _ => false,
+    }
+}
+
+fn number_in(s: &str, digits: usize, min: u32, max: u32) -> bool {
+    s.len() == digits && s.chars().all(|c| c.is_ascii_digit()) && s.parse::<u32>().is_ok_and(|n| n >= min && n <= max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(decl: &str) -> Result<FieldSpec, String> {
+        let table: toml::Table = toml::from_str(&format!("f = {}", decl)).unwrap();
+        FieldSpec::from_toml(&table["f"])
+    }
+
+    fn spec(decl: &str) -> FieldSpec {
+        parse(decl).unwrap()
+    }
+
+    #[test]
+    fn colors() {
+        for color in ["#fff", "#FFFA", "#1e90ff", "#1e90ff80", "tomato", "RebeccaPurple", "transparent",
+                      "rgb(30, 144, 255)", "rgba(30 144 255 / 50%)", "hsl(210deg 100% 56%)"] {
+            assert!(is_color(color), "{}", color);
+        }
+        for color in ["#12", "#ggg", "hello", "blu", "rgb(1, 2)", "rgb(a, b, c)", "cmyk(1, 2, 3, 4)", "rgb(1, 2, 3"] {
+            assert!(!is_color(color), "{}", color);
+        }
+    }
+
+    #[test]
+    fn datetimes() {
+        for datetime in ["2024-05-01", "2024-05-01T18:30", "2024-05-01 18:30:00Z", "2024-05-01T18:30:00.5+09:00",
+                         "2024-05-01T18:30-05:00"] {
+            assert!(is_datetime(datetime), "{}", datetime);
+        }
+        for datetime in ["2024-5-1", "2024-13-01", "2024-05-32", "2024-05-01T25:00", "2024-05-01T18", "tomorrow",
+                         "2024-05-01T18:30:00.", "2024-05-01T18:30+9"] {
+            assert!(!is_datetime(datetime), "{}", datetime);
+        }
+    }
+
+    #[test]
+    fn checks_by_type() {
+        let url = spec(r#"{ name = "url", type = "url" }"#);
+        assert_eq!(url.check(&json!("https://example.com")), None);
+        assert_eq!(url.check(&json!("")), None);
+        assert!(url.check(&json!("example.com")).is_some());
+
+        let zoom = spec(r#"{ name = "zoom", type = "integer", min = 0, max = 22 }"#);
+        assert_eq!(zoom.check(&json!(12)), None);
+        assert!(zoom.check(&json!(23)).is_some());
+        assert!(zoom.check(&json!("12")).is_some());
+
+        let platform = spec(r#"{ name = "platform", type = "enum", values = ["github", "x"] }"#);
+        assert_eq!(platform.check(&json!("x")), None);
+        assert!(platform.check(&json!("")).is_some());
+
+        let imgs = spec(r#"{ name = "imgs", type = "string[]", max_len = 2 }"#);
+        assert_eq!(imgs.check(&json!(["a", "b"])), None);
+        assert!(imgs.check(&json!(["a", "b", "c"])).is_some());
+        assert!(imgs.check(&json!([1])).is_some());
+
+        let links = spec(r#"{ name = "links", type = "object[]" }"#);
+        assert_eq!(links.check(&json!([{"url": "x"}])), None);
+        assert!(links.check(&json!(["x"])).is_some());
+    }
+
+    #[test]
+    fn optional_fields_accept_null() {
+        assert_eq!(spec(r#"{ name = "bgColor?", type = "color" }"#).check(&Value::Null), None);
+        assert!(spec(r#"{ name = "bgColor", type = "color" }"#).check(&Value::Null).is_some());
+        assert_eq!(spec(r#""title""#).check(&json!(42)), None);
+    }
+
+    #[test]
+    fn bad_declarations_are_rejected() {
+        for decl in [r#"{ name = "a", type = "enum" }"#, r#"{ name = "a", type = "integer", min = 3, max = 1 }"#,
+                     r#"{ name = "a", type = "color", default = "nope" }"#, r#"{ name = "a", kind = "url" }"#] {
+            assert!(parse(decl).is_err(), "{}", decl);
+        }
+    }
+}
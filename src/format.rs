@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use serde_json::Value;
+
+use std::path::Path;
+
+use crate::error::{line_column, toml_position, LoadError, LoadErrors};
+
+/// On-disk format of a content file. Cards are edited as JSON either way.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum Format {
+    #[default]
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+
+    /// Format chosen by file extension, JSON for anything else and stdin.
+    pub fn from_path(filename: &str) -> Format {
+        match Path::new(filename).extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => Format::Yaml,
+            Some("toml") => Format::Toml,
+            _ => Format::Json,
+        }
+    }
+
+    pub fn parse(&self, text: &str) -> Result<Value, LoadErrors> {
+        let value = match self {
+            Format::Json => serde_json::from_str(text).map_err(|e| LoadError::from_json(&e))?,
+            Format::Yaml => serde_yaml::from_str(text).map_err(|e| LoadError::from_yaml(&e))?,
+            Format::Toml => {
+                let table: toml::Table = toml::from_str(text).map_err(|e| LoadError::from_toml(&e, text))?;
+                toml_to_json(toml::Value::Table(table))
+            }
+        };
+        Ok(value)
+    }
+
+    /// Line and column of the profile (`None`) or of showcase card `card` in
+    /// a YAML or TOML document. JSON positions come from the scanner.
+    pub fn position(&self, text: &str, card: Option<usize>) -> Option<(usize, usize)> {
+        match self {
+            Format::Json => None,
+            Format::Yaml => yaml_offset(text, card).map(|offset| line_column(text, offset)),
+            Format::Toml => match card {
+                Some(k) => toml_position(text, &format!("showcase[{}]", k)),
+                None => toml_position(text, "profile"),
+            },
+        }
+    }
+
+    /// Canonical rendering of a document in this format.
+    pub fn write(&self, value: &Value) -> Result<String> {
+        let output = match self {
+            Format::Json => serde_json::to_string_pretty(value)?,
+            Format::Yaml => serde_yaml::to_string(value)?,
+            Format::Toml => {
+                let mut nulls = Vec::new();
+                find_nulls(value, String::new(), &mut nulls);
+                if !nulls.is_empty() {
+                    return Err(anyhow!("Cannot write as TOML, it has no null value: {}", nulls.join(", ")));
+                }
+                toml::to_string(value).map_err(|e| anyhow!("Cannot write as TOML: {}", e))?
+            }
+        };
+        Ok(output)
+    }
+
+}
+
+// Paths of null values, in the same notation as load errors
+fn find_nulls(value: &Value, path: String, nulls: &mut Vec<String>) {
+    match value {
+        Value::Null => nulls.push(path),
+        Value::Array(a) => {
+            for (i, v) in a.iter().enumerate() {
+                find_nulls(v, format!("{}[{}]", path, i), nulls);
+            }
+        }
+        Value::Object(o) => {
+            for (k, v) in o {
+                let key = if path.is_empty() { k.clone() } else { format!("{}.{}", path, k) };
+                find_nulls(v, key, nulls);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Block style only: top-level keys start their line, and cards are the
+// `- ` items right under `showcase:`
+fn yaml_offset(text: &str, card: Option<usize>) -> Option<usize> {
+    let mut offset = 0;
+    let mut in_showcase = false;
+    let mut item_indent = None;
+    let mut count = 0;
+    for line in text.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if trimmed.trim_end().is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if indent == 0 && !trimmed.starts_with('-') {
+            let key = trimmed.split(':').next().unwrap_or_default().trim_matches(|c| c == '"' || c == '\'');
+            if key == "profile" && card.is_none() {
+                return Some(start);
+            }
+            in_showcase = key == "showcase";
+            continue;
+        }
+        let is_item = trimmed.trim_end() == "-" || trimmed.starts_with("- ");
+        if in_showcase && is_item && *item_indent.get_or_insert(indent) == indent {
+            if card == Some(count) {
+                return Some(start + indent);
+            }
+            count += 1;
+        }
+    }
+    None
+}
+
+// Datetimes become strings, serde would turn them into a private marker table
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => serde_json::Number::from_f64(f).map_or(Value::Null, Value::Number),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(a) => Value::Array(a.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(t) => Value::Object(t.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toml_datetimes_become_strings() {
+        let value = Format::Toml.parse("b = 1\na = 2024-05-01T10:00:00Z\n").unwrap();
+        assert_eq!(value, serde_json::json!({"b": 1, "a": "2024-05-01T10:00:00Z"}));
+        assert_eq!(value.as_object().unwrap().keys().collect::<Vec<_>>(), ["b", "a"]);
+    }
+
+    #[test]
+    fn toml_output_names_every_null() {
+        let value = serde_json::json!({
+            "profile": {"name": "Ann", "bio": null},
+            "showcase": [{"Section": {"title": "Hi"}}, {"Section": {"title": "Yo", "subtitle": null}}]
+        });
+        let error = Format::Toml.write(&value).unwrap_err().to_string();
+        assert_eq!(error, "Cannot write as TOML, it has no null value: profile.bio, showcase[1].Section.subtitle");
+    }
+
+    #[test]
+    fn yaml_and_toml_cards_are_located() {
+        let yaml = "# page\nprofile:\n  name: Ann\nshowcase:\n- Note:\n    title: a\n    tags:\n    - b\n\n- Link: {}\n";
+        assert_eq!(Format::Yaml.position(yaml, None), Some((2, 1)));
+        assert_eq!(Format::Yaml.position(yaml, Some(1)), Some((10, 1)));
+        assert_eq!(Format::Yaml.position(yaml, Some(2)), None);
+
+        let toml = "[profile]\nname = \"Ann\"\n\n[[showcase]]\n[showcase.Note]\n\n[[showcase]]\nLink = 3\n";
+        assert_eq!(Format::Toml.position(toml, None), Some((1, 1)));
+        assert_eq!(Format::Toml.position(toml, Some(1)), Some((7, 1)));
+    }
+}
@@ -0,0 +1,410 @@
+use anyhow::Result;
+
+use crate::error::{line_column, LoadError, LoadErrors};
+
+/// Comments found in a content file, grouped by what they belong to.
+///
+/// A comment belongs to the profile or card that follows it, that it sits
+/// inside of, or that ends earlier on its line. Anything else, such as a file
+/// header, goes to `header`.
+#[derive(Debug, Clone, Default)]
+pub struct Comments {
+    pub header: Vec<String>,
+    pub profile: Vec<String>,
+    pub cards: Vec<Vec<String>>,
+}
+
+/// A relaxed JSON document turned into strict JSON.
+pub struct Scanned {
+    pub json: String,
+    pub comments: Comments,
+    // Byte offsets of the "profile" key and of each showcase element
+    profile_at: Option<usize>,
+    cards_at: Vec<usize>,
+}
+
+#[derive(PartialEq)]
+enum Container {
+    Object { expect_key: bool },
+    Array,
+}
+
+// Where the scanner is, relative to the profile and the showcase cards
+#[derive(Clone, Copy)]
+enum Owner {
+    Header,
+    Profile,
+    Card(usize),
+}
+
+/// Accept JSON with `//` and `/* */` comments, trailing commas, unquoted keys
+/// and single-quoted strings. This is JSONC plus a subset of JSON5.
+///
+/// The strict JSON keeps the layout of the input, comments become spaces.
+pub fn scan(text: &str) -> Result<Scanned, LoadErrors> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut json = String::with_capacity(text.len());
+    let mut stack: Vec<Container> = vec![];
+    let mut root_key = String::new();
+    let mut comments = Comments::default();
+    let mut pending: Vec<String> = vec![];
+    let mut owner = Owner::Header;
+    // Owner of the element that ended on the current line, if any
+    let mut trailing: Option<Owner> = None;
+    let mut profile_at = None;
+    let mut cards_at = vec![];
+    let mut comma: Option<String> = None;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let (offset, c) = chars[i];
+        let next = chars.get(i + 1).map(|(_, c)| *c);
+
+        // Comments
+        if c == '/' && (next == Some('/') || next == Some('*')) {
+            let end = if next == Some('/') {
+                chars[i..].iter().position(|(_, c)| *c == '\n').map_or(chars.len(), |p| i + p)
+            } else {
+                let close = (i + 2..chars.len().saturating_sub(1))
+                    .find(|&j| chars[j].1 == '*' && chars[j + 1].1 == '/')
+                    .ok_or_else(|| LoadError::new("Unterminated comment").at(Some(line_column(text, offset))))?;
+                close + 2
+            };
+            let end_offset = chars.get(end).map_or(text.len(), |(o, _)| *o);
+            let comment = text[offset..end_offset].trim_end().to_string();
+            // Keep line breaks so positions in errors still match the file
+            json.extend(text[offset..end_offset].chars().map(|c| if c == '\n' { '\n' } else { ' ' }));
+            match trailing.unwrap_or(owner) {
+                Owner::Profile => comments.profile.push(comment),
+                Owner::Card(k) => comments.cards[k].push(comment),
+                Owner::Header => pending.push(comment),
+            }
+            i = end;
+            continue;
+        }
+
+        if c.is_whitespace() {
+            if c == '\n' {
+                trailing = None;
+            }
+            match &mut comma {
+                Some(held) => held.push(c),
+                None => json.push(c),
+            }
+            i += 1;
+            continue;
+        }
+
+        // A held comma is dropped when the container closes right after it
+        if let Some(held) = comma.take() {
+            if c == '}' || c == ']' {
+                json.push_str(&held[1..]);
+            } else {
+                json.push_str(&held);
+            }
+        }
+
+        if c != ',' {
+            trailing = None;
+        }
+
+        // Entering a value in the showcase array starts a new card
+        let in_showcase = stack.len() == 2 && stack[1] == Container::Array && root_key == "showcase";
+        if in_showcase && c != ']' && c != ',' {
+            comments.cards.push(std::mem::take(&mut pending));
+            cards_at.push(offset);
+            owner = Owner::Card(comments.cards.len() - 1);
+        }
+
+        let expect_key = matches!(stack.last(), Some(Container::Object { expect_key: true }));
+        match c {
+            '"' | '\'' => {
+                let (string, end) = read_string(text, &chars, i)?;
+                if expect_key && stack.len() == 1 {
+                    start_root_key(&string, offset, &mut root_key, &mut pending, &mut comments, &mut owner, &mut profile_at);
+                }
+                json.push_str(&quote(&string));
+                i = end;
+                continue;
+            }
+            '{' => {
+                // Comments before the document itself are its header
+                if stack.is_empty() {
+                    comments.header.append(&mut pending);
+                }
+                stack.push(Container::Object { expect_key: true });
+            }
+            '[' => stack.push(Container::Array),
+            '}' | ']' => {
+                stack.pop();
+                if c == '}' && stack.len() <= 2 {
+                    trailing = Some(owner);
+                }
+                if stack.len() == 1 {
+                    owner = Owner::Header;
+                }
+            }
+            ':' => {
+                if let Some(Container::Object { expect_key }) = stack.last_mut() {
+                    *expect_key = false;
+                }
+            }
+            ',' => {
+                if let Some(Container::Object { expect_key }) = stack.last_mut() {
+                    *expect_key = true;
+                }
+                // Comments after a comma belong to what follows it, unless
+                // they sit on the same line
+                if stack.len() == 1 || in_showcase {
+                    trailing = trailing.or(Some(owner));
+                    owner = Owner::Header;
+                }
+                comma = Some(",".to_string());
+                i += 1;
+                continue;
+            }
+            _ if expect_key && (c.is_alphabetic() || c == '_' || c == '$') => {
+                let end = (i..chars.len())
+                    .find(|&j| !(chars[j].1.is_alphanumeric() || chars[j].1 == '_' || chars[j].1 == '$'))
+                    .unwrap_or(chars.len());
+                let end_offset = chars.get(end).map_or(text.len(), |(o, _)| *o);
+                let key = &text[offset..end_offset];
+                if stack.len() == 1 {
+                    start_root_key(key, offset, &mut root_key, &mut pending, &mut comments, &mut owner, &mut profile_at);
+                }
+                json.push_str(&quote(key));
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        json.push(c);
+        i += 1;
+    }
+    if let Some(held) = comma {
+        json.push_str(&held);
+    }
+
+    comments.header.append(&mut pending);
+    Ok(Scanned { json, comments, profile_at, cards_at })
+}
+
+/// Write comments back into a strict JSON document, each on its own line
+/// above the profile or card it belongs to. Header comments go on top.
+pub fn insert_comments(json: &str, comments: &Comments) -> Result<String> {
+    let scanned = scan(json)?;
+
+    let mut inserts: Vec<(usize, &[String])> = vec![];
+    if let Some(at) = scanned.profile_at {
+        inserts.push((at, &comments.profile));
+    }
+    for (at, card) in scanned.cards_at.iter().zip(&comments.cards) {
+        inserts.push((*at, card));
+    }
+    inserts.sort_by_key(|(at, _)| *at);
+
+    let mut output = String::new();
+    for comment in &comments.header {
+        output.push_str(comment);
+        output.push('\n');
+    }
+    let mut last = 0;
+    for (at, lines) in inserts {
+        if lines.is_empty() {
+            continue;
+        }
+        let line_start = json[..at].rfind('\n').map_or(0, |p| p + 1);
+        let indent = &json[line_start..at];
+        if !indent.trim().is_empty() {
+            // Not at the start of a line, as in single-line documents
+            output.push_str(&json[last..at]);
+            for comment in lines {
+                output.push_str(&block_comment(comment));
+                output.push(' ');
+            }
+            last = at;
+            continue;
+        }
+        output.push_str(&json[last..line_start]);
+        for comment in lines {
+            output.push_str(indent);
+            output.push_str(comment);
+            output.push('\n');
+        }
+        last = line_start;
+    }
+    output.push_str(&json[last..]);
+    Ok(output)
+}
+
+impl Scanned {
+
+    /// Line and column of the profile key, or of showcase card `card`.
+    pub fn position(&self, text: &str, card: Option<usize>) -> Option<(usize, usize)> {
+        let offset = match card {
+            Some(k) => self.cards_at.get(k).copied(),
+            None => self.profile_at,
+        }?;
+        Some(line_column(text, offset))
+    }
+
+}
+
+impl Comments {
+
+    pub fn is_empty(&self) -> bool {
+        self.header.is_empty() && self.profile.is_empty() && self.cards.iter().all(|c| c.is_empty())
+    }
+
+}
+
+fn start_root_key(
+    key: &str,
+    offset: usize,
+    root_key: &mut String,
+    pending: &mut Vec<String>,
+    comments: &mut Comments,
+    owner: &mut Owner,
+    profile_at: &mut Option<usize>,
+) {
+    *root_key = key.to_string();
+    if key == "profile" {
+        comments.profile.append(pending);
+        *profile_at = Some(offset);
+        *owner = Owner::Profile;
+    } else {
+        comments.header.append(pending);
+        *owner = Owner::Header;
+    }
+}
+
+// Read a double or single quoted string starting at `chars[start]`, returning
+// its value and the index right after the closing quote
+fn read_string(text: &str, chars: &[(usize, char)], start: usize) -> Result<(String, usize), LoadErrors> {
+    let quote = chars[start].1;
+    let mut raw = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        let c = chars[i].1;
+        if c == quote {
+            let escaped = if quote == '"' {
+                raw
+            } else {
+                raw.replace("\\'", "'").replace('"', "\\\"")
+            };
+            return match serde_json::from_str(&format!("\"{}\"", escaped)) {
+                Ok(value) => Ok((value, i + 1)),
+                Err(e) => {
+                    let message = format!("Invalid string: {}", LoadError::from_json(&e).message);
+                    Err(LoadError::new(&message).at(Some(line_column(text, chars[start].0))).into())
+                }
+            };
+        }
+        raw.push(c);
+        if c == '\\' {
+            if let Some((_, escaped)) = chars.get(i + 1) {
+                raw.push(*escaped);
+                i += 1;
+            }
+        }
+        i += 1;
+    }
+    Err(LoadError::new("Unterminated string").at(Some(line_column(text, chars[start].0))).into())
+}
+
+fn block_comment(comment: &str) -> String {
+    match comment.strip_prefix("//") {
+        Some(body) => format!("/*{} */", body),
+        None => comment.to_string(),
+    }
+}
+
+fn quote(s: &str) -> String {
+    serde_json::Value::from(s).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn strict(text: &str) -> Value {
+        serde_json::from_str(&scan(text).unwrap().json).unwrap()
+    }
+
+    #[test]
+    fn trailing_commas_are_dropped() {
+        let value = strict("{\"a\": [1, 2,], \"b\": {\"c\": 1,\n},\n}");
+        assert_eq!(value, json!({"a": [1, 2], "b": {"c": 1}}));
+    }
+
+    #[test]
+    fn unquoted_keys_and_single_quotes() {
+        let value = strict(r#"{a: 'it\'s "quoted"', $b_1: 'é\n'}"#);
+        assert_eq!(value, json!({"a": "it's \"quoted\"", "$b_1": "é\n"}));
+    }
+
+    #[test]
+    fn comment_markers_inside_strings_are_kept() {
+        let scanned = scan("{\"url\": \"https://example.com/*x*/\", 'b': '//' // end\n}").unwrap();
+        let value: Value = serde_json::from_str(&scanned.json).unwrap();
+        assert_eq!(value, json!({"url": "https://example.com/*x*/", "b": "//"}));
+        assert_eq!(scanned.comments.header, vec!["// end"]);
+    }
+
+    #[test]
+    fn comments_belong_to_the_following_card() {
+        let text = "// file\n{\n  // me\n  \"profile\": {},\n  \"showcase\": [\n    // first\n    {\"Note\": {}},\n    {\"Link\": {\n      /* inside */\n    }}\n  ]\n}";
+        let comments = scan(text).unwrap().comments;
+        assert_eq!(comments.header, vec!["// file"]);
+        assert_eq!(comments.profile, vec!["// me"]);
+        assert_eq!(comments.cards, vec![vec!["// first".to_string()], vec!["/* inside */".to_string()]]);
+    }
+
+    #[test]
+    fn comments_at_the_end_of_a_line_belong_to_what_ends_there() {
+        let text = "{\n  \"profile\": {\"name\": \"Ann\", // name\n  }, // me\n  \"showcase\": [\n    {\"Note\": {}}, // the note\n    {\"Link\": {}} /* the link */\n  ] // end\n}";
+        let comments = scan(text).unwrap().comments;
+        assert_eq!(comments.profile, vec!["// name", "// me"]);
+        assert_eq!(comments.cards, vec![vec!["// the note".to_string()], vec!["/* the link */".to_string()]]);
+        assert_eq!(comments.header, vec!["// end"]);
+    }
+
+    #[test]
+    fn comments_move_with_their_cards() {
+        let text = "{\n  \"showcase\": [\n    // link\n    {\"Link\": {}},\n    {\"Note\": {}}\n  ]\n}";
+        let mut comments = scan(text).unwrap().comments;
+        assert_eq!(comments.cards, vec![vec!["// link".to_string()], vec![]]);
+
+        // Swap the cards, their comments are stored alongside them
+        comments.cards.swap(0, 1);
+        let json = "{\n  \"showcase\": [\n    {\"Note\": {}},\n    {\"Link\": {}}\n  ]\n}";
+        assert_eq!(
+            insert_comments(json, &comments).unwrap(),
+            "{\n  \"showcase\": [\n    {\"Note\": {}},\n    // link\n    {\"Link\": {}}\n  ]\n}"
+        );
+    }
+
+    #[test]
+    fn single_line_documents_get_block_comments() {
+        let comments = Comments {
+            header: vec![],
+            profile: vec!["// me".to_string()],
+            cards: vec![vec!["/* note */".to_string()]],
+        };
+        let json = r#"{"profile": {}, "showcase": [{"Note": {}}]}"#;
+        let output = insert_comments(json, &comments).unwrap();
+        assert_eq!(output, r#"{/* me */ "profile": {}, "showcase": [/* note */ {"Note": {}}]}"#);
+        assert_eq!(scan(&output).unwrap().comments.cards, comments.cards);
+    }
+
+    #[test]
+    fn unterminated_comments_and_strings_are_located() {
+        let error = scan("{\n  /* open\n}").err().unwrap();
+        assert_eq!((error.0[0].line, error.0[0].column), (Some(2), Some(3)));
+        assert_eq!(error.0[0].message, "Unterminated comment");
+
+        let error = scan("{'a: 1}").err().unwrap();
+        assert_eq!(error.0[0].message, "Unterminated string");
+    }
+}
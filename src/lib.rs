@@ -2,8 +2,20 @@ mod arrange;
 mod card;
 mod config;
 mod data;
+mod diff;
+mod error;
+mod field;
+mod format;
+mod jsonc;
+mod merge;
+mod migrate;
+mod ops;
+mod state;
+mod storage;
+mod validate;
 
 pub mod app;
+pub mod cli;
 
 #[macro_export]
 macro_rules! write_info {
@@ -1,11 +1,13 @@
-use obentou_cli::app::App;
+use obentou_cli::cli::{self, Cli};
+
+use clap::Parser;
+
+use std::process::ExitCode;
 
 use anyhow::Result;
 
-fn main() -> Result<()> {
-    let mut app = App::new(std::env::args().nth(1).unwrap_or_default())?;
-    app.run()?;
-    Ok(())
+fn main() -> Result<ExitCode> {
+    cli::run(Cli::parse())
 }
 
 /*
@@ -0,0 +1,244 @@
+use crate::data::{Data, Item};
+use crate::diff::match_cards;
+
+/// Which version of a card wins a merge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Local,
+    Disk,
+}
+
+/// A card that differs between the open document and the file on disk.
+///
+/// `local` and `disk` are item indices on each side, `None` when the card
+/// does not exist there. Whether a side changed is judged against the
+/// version both started from.
+#[derive(Debug, Clone)]
+pub struct MergeEntry {
+    pub local: Option<usize>,
+    pub disk: Option<usize>,
+    pub local_changed: bool,
+    pub disk_changed: bool,
+    pub choice: Side,
+}
+
+/// Every card that differs between `local` and `disk`, in document order.
+///
+/// A card changed on one side only defaults to that side, a card changed on
+/// both defaults to the local version.
+pub fn merge_entries(base: &Data, local: &Data, disk: &Data) -> Vec<MergeEntry> {
+    let base_local = match_cards(base, local);
+    let base_disk = match_cards(base, disk);
+    let base_of_local = |i: usize| if i == 0 { Some(0) } else { base_local.iter().find(|p| p.1 == i).map(|p| p.0) };
+    let base_of_disk = |j: usize| if j == 0 { Some(0) } else { base_disk.iter().find(|p| p.1 == j).map(|p| p.0) };
+
+    let mut entries = vec![];
+    for (l, d) in slots(local, disk) {
+        if let (Some(i), Some(j)) = (l, d) {
+            if same_card(&local.items[i], &disk.items[j]) {
+                continue;
+            }
+        }
+
+        let local_base = l.and_then(base_of_local).or_else(|| d.and_then(base_of_disk));
+        let disk_base = d.and_then(base_of_disk).or_else(|| l.and_then(base_of_local));
+        let changed = |side: Option<&Item>, base_index: Option<usize>| match (side, base_index) {
+            (Some(item), Some(b)) => !same_card(item, &base.items[b]),
+            (None, None) => false,
+            _ => true,
+        };
+        let local_changed = changed(l.map(|i| &local.items[i]), local_base);
+        let disk_changed = changed(d.map(|j| &disk.items[j]), disk_base);
+
+        entries.push(MergeEntry {
+            local: l,
+            disk: d,
+            local_changed,
+            disk_changed,
+            choice: if disk_changed && !local_changed { Side::Disk } else { Side::Local },
+        });
+    }
+    entries
+}
+
+/// Build the merged document. Cards that are the same on both sides are kept,
+/// the others come from the side chosen in `entries`.
+pub fn apply_merge(local: &Data, disk: &Data, entries: &[MergeEntry]) -> Data {
+    let mut items = vec![];
+    for (l, d) in slots(local, disk) {
+        let choice = entries
+            .iter()
+            .find(|e| e.local == l && e.disk == d)
+            .map_or(Side::Local, |e| e.choice);
+        let item = match choice {
+            Side::Local => l.map(|i| &local.items[i]),
+            Side::Disk => d.map(|j| &disk.items[j]),
+        };
+        if let Some(item) = item {
+            items.push(item.clone());
+        }
+    }
+
+    // Keys outside the cards come from disk, the TUI never edits them
+    Data {
+        metadata: local.metadata.clone(),
+        items,
+        document: disk.document.clone(),
+    }
+}
+
+impl MergeEntry {
+
+    pub fn describe(&self, local: &Data, disk: &Data) -> String {
+        let item = match (self.local, self.disk) {
+            (Some(i), _) => &local.items[i],
+            (None, Some(j)) => &disk.items[j],
+            (None, None) => return String::new(),
+        };
+        let name = match item.label() {
+            Some(label) => format!("{} ({})", item.get_title(), label),
+            None => item.get_title().to_string(),
+        };
+        let status = match (self.local.is_some(), self.disk.is_some(), self.local_changed, self.disk_changed) {
+            (true, true, true, true) => "edited on both sides",
+            (true, true, true, false) => "edited here",
+            (true, true, false, _) => "edited on disk",
+            (true, false, true, true) => "edited here, deleted on disk",
+            (true, false, false, true) => "deleted on disk",
+            (true, false, _, false) => "added here",
+            (false, true, true, true) => "deleted here, edited on disk",
+            (false, true, true, false) => "deleted here",
+            (false, true, false, _) => "added on disk",
+            (false, false, _, _) => "",
+        };
+        let choice = match self.choice {
+            Side::Local => "here",
+            Side::Disk => "disk",
+        };
+        format!("[{}] {}: {}", choice, name, status)
+    }
+
+    pub fn toggle(&mut self) {
+        self.choice = match self.choice {
+            Side::Local => Side::Disk,
+            Side::Disk => Side::Local,
+        };
+    }
+
+    pub fn is_conflict(&self) -> bool {
+        self.local_changed && self.disk_changed
+    }
+
+}
+
+// Cards of both sides paired up, in local order. Cards only on disk follow
+// the local card that comes before them on disk.
+fn slots(local: &Data, disk: &Data) -> Vec<(Option<usize>, Option<usize>)> {
+    let mut pairs = vec![(0, 0)];
+    pairs.extend(match_cards(local, disk));
+
+    let mut keyed = vec![];
+    for i in 0..local.items.len() {
+        let d = pairs.iter().find(|p| p.0 == i).map(|p| p.1);
+        keyed.push(((i, 0, 0), (Some(i), d)));
+    }
+    for j in 1..disk.items.len() {
+        if pairs.iter().any(|p| p.1 == j) {
+            continue;
+        }
+        let anchor = pairs
+            .iter()
+            .filter(|p| p.1 < j)
+            .max_by_key(|p| p.1)
+            .map_or(0, |p| p.0);
+        keyed.push(((anchor, 1, j), (None, Some(j))));
+    }
+
+    keyed.sort_by_key(|(key, _)| *key);
+    keyed.into_iter().map(|(_, slot)| slot).collect()
+}
+
+fn same_card(a: &Item, b: &Item) -> bool {
+    a.get_title() == b.get_title()
+        && a.get_shape() == b.get_shape()
+        && match (a.to_value(), b.to_value()) {
+            (Ok(x), Ok(y)) => x == y,
+            _ => a.get_lines() == b.get_lines(),
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::tests::{note, showcase};
+
+    fn notes(cards: &[(&str, &str)]) -> Data {
+        showcase(&cards.iter().map(|(title, content)| note(title, content)).collect::<Vec<_>>())
+    }
+
+    fn contents(data: &Data) -> Vec<String> {
+        data.items[1..]
+            .iter()
+            .map(|item| item.to_value().unwrap()["content"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn slots_pair_matching_cards() {
+        let local = notes(&[("a", "1"), ("b", "2")]);
+        assert_eq!(slots(&local, &local), vec![(Some(0), Some(0)), (Some(1), Some(1)), (Some(2), Some(2))]);
+    }
+
+    #[test]
+    fn slots_put_disk_only_cards_after_their_neighbour() {
+        let local = notes(&[("a", "1"), ("c", "3")]);
+        let disk = notes(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        assert_eq!(
+            slots(&local, &disk),
+            vec![(Some(0), Some(0)), (Some(1), Some(1)), (None, Some(2)), (Some(2), Some(3))]
+        );
+    }
+
+    #[test]
+    fn unchanged_documents_need_no_merge() {
+        let base = notes(&[("a", "1"), ("b", "2")]);
+        assert!(merge_entries(&base, &base, &base).is_empty());
+    }
+
+    #[test]
+    fn one_sided_edits_default_to_the_edited_side() {
+        let base = notes(&[("a", "1"), ("b", "2")]);
+        let local = notes(&[("a", "local"), ("b", "2")]);
+        let disk = notes(&[("a", "1"), ("b", "disk")]);
+        let entries = merge_entries(&base, &local, &disk);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].choice, entries[0].is_conflict()), (Side::Local, false));
+        assert_eq!((entries[1].choice, entries[1].is_conflict()), (Side::Disk, false));
+        assert_eq!(contents(&apply_merge(&local, &disk, &entries)), vec!["local", "disk"]);
+    }
+
+    #[test]
+    fn edits_on_both_sides_conflict() {
+        let base = notes(&[("a", "1")]);
+        let local = notes(&[("a", "local")]);
+        let disk = notes(&[("a", "disk")]);
+        let mut entries = merge_entries(&base, &local, &disk);
+
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].is_conflict());
+        assert_eq!(contents(&apply_merge(&local, &disk, &entries)), vec!["local"]);
+        entries[0].toggle();
+        assert_eq!(contents(&apply_merge(&local, &disk, &entries)), vec!["disk"]);
+    }
+
+    #[test]
+    fn additions_and_deletions_are_kept() {
+        let base = notes(&[("a", "1"), ("b", "2")]);
+        let local = notes(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        let disk = notes(&[("b", "2")]);
+        let entries = merge_entries(&base, &local, &disk);
+
+        assert_eq!(contents(&apply_merge(&local, &disk, &entries)), vec!["2", "3"]);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::data::Data;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use std::collections::BTreeMap;
+
+/// Version of content files written before they carried a `version` key.
+pub const FIRST_VERSION: u64 = 1;
+
+/// One step of a schema upgrade, declared under `[[Migrations]]`.
+///
+/// Steps run in order of `version`, the version a document has once the step
+/// is applied. A step touches every showcase card, or only cards of type
+/// `card`, with "Profile" standing for the profile.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Migration {
+    pub version: u64,
+    pub card: Option<String>,
+    /// New card type for the matching cards
+    pub retype: Option<String>,
+    /// Fields to rename, old name = new name
+    #[serde(default)]
+    pub rename: BTreeMap<String, String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// Values for fields the cards do not have yet, or left as ""
+    #[serde(default)]
+    pub default: Map<String, Value>,
+}
+
+impl Migration {
+
+    /// Parse one `[[Migrations]]` entry, rejecting steps that change nothing.
+    pub fn from_toml(value: &toml::Value) -> Result<Migration, String> {
+        let step: Migration = value.clone().try_into().map_err(|e: toml::de::Error| e.message().trim().to_string())?;
+        if step.retype.is_none() && step.rename.is_empty() && step.remove.is_empty() && step.default.is_empty() {
+            return Err(format!("Migration to version {} changes nothing", step.version));
+        }
+        Ok(step)
+    }
+
+}
+
+/// Bring `data` up to the schema version of its metadata, returning the
+/// version it started from. Nothing is changed when it is already current.
+pub fn migrate_data(data: &mut Data) -> Result<u64> {
+    let from = data.get_version();
+    let to = data.metadata.get_version();
+    if from > to {
+        return Err(anyhow!("Document is at version {}, newer than the metadata's version {}", from, to));
+    }
+
+    let mut steps: Vec<Migration> = data
+        .metadata
+        .get_migrations()
+        .iter()
+        .filter(|m| m.version > from && m.version <= to)
+        .cloned()
+        .collect();
+    steps.sort_by_key(|m| m.version);
+
+    for step in &steps {
+        for (i, item) in data.items.iter_mut().enumerate() {
+            let matches = match &step.card {
+                Some(card) => item.get_title() == card,
+                None => i != 0,
+            };
+            if !matches {
+                continue;
+            }
+
+            let mut value = item.to_value()?;
+            let fields = value
+                .as_object_mut()
+                .with_context(|| format!("Card {} is not a json object", i))?;
+            // Renamed fields keep their place among the others
+            *fields = std::mem::take(fields)
+                .into_iter()
+                .map(|(k, v)| (step.rename.get(&k).cloned().unwrap_or(k), v))
+                .collect();
+            for field in &step.remove {
+                fields.remove(field);
+            }
+            for (field, v) in &step.default {
+                if fields.get(field).is_none_or(|old| *old == "") {
+                    fields.insert(field.to_string(), v.clone());
+                }
+            }
+
+            if let Some(card_type) = &step.retype {
+                if i != 0 {
+                    item.set_title(card_type.to_string());
+                }
+            }
+            item.set_lines_and_format(&[serde_json::to_string_pretty(&value)?]);
+        }
+    }
+
+    if from < to {
+        data.set_version(to);
+    }
+    Ok(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::data::tests::{note, showcase};
+    use crate::validate::validate_data;
+
+    fn with_steps(data: &mut Data, version: u64, steps: &str) {
+        let table: toml::Table = toml::from_str(steps).unwrap();
+        let migrations: Vec<Migration> = table["Migrations"].clone().try_into().unwrap();
+        data.metadata.set_schema(version, migrations);
+    }
+
+    fn keys(data: &Data, i: usize) -> Vec<String> {
+        data.items[i].to_value().unwrap().as_object().unwrap().keys().cloned().collect()
+    }
+
+    #[test]
+    fn steps_run_in_version_order() {
+        let mut data = showcase(&[note("a", "1")]);
+        with_steps(&mut data, 3, r#"
+            [[Migrations]]
+            version = 3
+            rename = { heading = "headline" }
+
+            [[Migrations]]
+            version = 2
+            rename = { title = "heading" }
+        "#);
+        assert_eq!(migrate_data(&mut data).unwrap(), 1);
+        assert_eq!(data.get_version(), 3);
+        assert_eq!(keys(&data, 1), ["headline", "content"]);
+    }
+
+    #[test]
+    fn steps_already_applied_are_skipped() {
+        let mut data = showcase(&[note("a", "1")]);
+        data.set_version(2);
+        with_steps(&mut data, 3, r#"
+            [[Migrations]]
+            version = 2
+            remove = ["title"]
+
+            [[Migrations]]
+            version = 3
+            default = { title = "kept", extra = true }
+        "#);
+        migrate_data(&mut data).unwrap();
+        assert_eq!(data.items[1].to_value().unwrap()["title"], "a");
+        assert_eq!(keys(&data, 1), ["title", "content", "extra"]);
+    }
+
+    #[test]
+    fn profile_steps_only_touch_the_profile() {
+        let mut data = showcase(&[note("a", "1")]);
+        with_steps(&mut data, 2, r#"
+            [[Migrations]]
+            version = 2
+            card = "Profile"
+            default = { pronouns = "" }
+            retype = "Person"
+        "#);
+        migrate_data(&mut data).unwrap();
+        assert_eq!(data.items[0].get_title(), "Profile");
+        assert_eq!(keys(&data, 0), ["pronouns"]);
+        assert_eq!(keys(&data, 1), ["title", "content"]);
+    }
+
+    #[test]
+    fn newer_documents_are_rejected() {
+        let mut data = showcase(&[note("a", "1")]);
+        data.set_version(3);
+        with_steps(&mut data, 2, "Migrations = []");
+        assert!(migrate_data(&mut data).is_err());
+        assert_eq!(data.get_version(), 3);
+    }
+
+    // Cards with problems, the test profile is empty
+    fn card_problems(data: &Data) -> Vec<usize> {
+        validate_data(data).iter().map(|d| d.index).filter(|&i| i != 0).collect()
+    }
+
+    #[test]
+    fn legacy_empty_values_become_typed_defaults() {
+        let mut data = showcase(&[
+            r#"{"Map": {"content": "", "address": "Kyoto", "zoom": ""}}"#.to_string(),
+            r#"{"Map": {"content": "", "address": "Nara", "zoom": 15}}"#.to_string(),
+            r#"{"Album": {"imgs": ""}}"#.to_string(),
+        ]);
+        assert_eq!(card_problems(&data), [1, 3]);
+
+        assert_eq!(migrate_data(&mut data).unwrap(), 1);
+        assert_eq!(data.items[1].to_value().unwrap()["zoom"], 12);
+        assert_eq!(data.items[2].to_value().unwrap()["zoom"], 15);
+        assert_eq!(data.items[3].to_value().unwrap()["imgs"], serde_json::json!([]));
+        assert_eq!(keys(&data, 1), ["content", "address", "zoom"]);
+        assert!(card_problems(&data).is_empty());
+    }
+
+    #[test]
+    fn steps_must_name_known_card_types() {
+        let steps = r#"
+            [[Migrations]]
+            version = 2
+            card = "Photo"
+            retype = "Picture"
+
+            [[Migrations]]
+            version = 3
+            card = "Sticker"
+            remove = ["url"]
+
+            [[Migrations]]
+            version = 4
+            card = "Profile"
+            remove = ["avatar"]
+        "#;
+        let shipped = include_str!("../metadata.toml");
+        let first = Config::parse(shipped).unwrap().get_metadata().get_migrations().len();
+        let errors = Config::parse(&format!("{}\n{}", shipped, steps)).err().unwrap();
+        let paths: Vec<_> = errors.0.iter().map(|e| e.path.clone().unwrap()).collect();
+        assert_eq!(paths, [format!("Migrations[{}].retype", first), format!("Migrations[{}].card", first + 1)]);
+        assert_eq!(errors.0[0].message, "Unknown card type \"Picture\"");
+    }
+}
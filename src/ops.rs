@@ -0,0 +1,162 @@
+use crate::data::{read_source, Data, DEFAULT_SHAPE};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use std::path::Path;
+
+/// A batch of edits, read from JSON or TOML.
+///
+/// Each operation sees the document as left by the previous ones, and card
+/// indices are the ones shown by `list`.
+#[derive(Debug, Deserialize)]
+pub struct OpsFile {
+    pub ops: Vec<Op>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Op {
+    /// Create a card, appended to the showcase unless `index` is given
+    Insert {
+        card: String,
+        index: Option<usize>,
+        #[serde(default = "default_shape")]
+        shape: String,
+        #[serde(default)]
+        fields: Map<String, Value>,
+    },
+    /// Set one field of a card, 0 being the profile
+    Set {
+        index: usize,
+        field: String,
+        value: Value,
+    },
+    Reshape {
+        index: usize,
+        shape: String,
+    },
+    Move {
+        from: usize,
+        to: usize,
+    },
+    /// Delete the card at `index`, or every card of type `card`
+    Delete {
+        index: Option<usize>,
+        card: Option<String>,
+    },
+}
+
+fn default_shape() -> String {
+    DEFAULT_SHAPE.to_string()
+}
+
+pub fn load_ops(filename: &str) -> Result<OpsFile> {
+    let content = read_source(filename)?;
+    let ops = match Path::new(filename).extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&content)?,
+        _ => serde_json::from_str(&content)?,
+    };
+    Ok(ops)
+}
+
+/// Apply every operation or none: on error `data` is left untouched.
+pub fn apply_ops(data: &mut Data, ops: &[Op]) -> Result<()> {
+    let mut result = data.clone();
+    for (i, op) in ops.iter().enumerate() {
+        apply_op(&mut result, op).with_context(|| format!("Operation #{} failed", i + 1))?;
+    }
+    *data = result;
+    Ok(())
+}
+
+fn apply_op(data: &mut Data, op: &Op) -> Result<()> {
+    match op {
+        Op::Insert { card, index, shape, fields } => {
+            let idx = data.insert_card(index.unwrap_or(data.items.len()), card, shape)?;
+            for (field, value) in fields {
+                set_field(data, idx, field, value)?;
+            }
+            data.check_item(idx)
+        }
+        Op::Set { index, field, value } => {
+            set_field(data, *index, field, value)?;
+            data.check_item(*index)
+        }
+        Op::Reshape { index, shape } => data.reshape_card(*index, shape),
+        Op::Move { from, to } => data.move_card(*from, *to),
+        Op::Delete { index: Some(index), card } => {
+            let item = data.items.get(*index).context(format!("Card index {} out of range", index))?;
+            if let Some(card) = card {
+                if item.get_title() != card {
+                    return Err(anyhow!("Card {} is a {}, not a {}", index, item.get_title(), card));
+                }
+            }
+            data.remove_card(*index)?;
+            Ok(())
+        }
+        Op::Delete { index: None, card: Some(card) } => {
+            if data.metadata.find_card(card).is_none() {
+                return Err(anyhow!("No such card type: {}", card));
+            }
+            // Never drop the profile, which is always the first item
+            let mut idx = 0;
+            data.items.retain(|item| {
+                idx += 1;
+                idx == 1 || item.get_title() != card
+            });
+            Ok(())
+        }
+        Op::Delete { index: None, card: None } => Err(anyhow!("Delete needs an index or a card type")),
+    }
+}
+
+fn set_field(data: &mut Data, idx: usize, field: &str, value: &Value) -> Result<()> {
+    let item = data.items.get_mut(idx).context(format!("Card index {} out of range", idx))?;
+    let declared = data
+        .metadata
+        .get_field(item.get_title())
+        .context(format!("No such field for card type '{}'", item.get_title()))?
+        .iter()
+        .any(|f| f.name == field);
+    if !declared {
+        return Err(anyhow!("Card type '{}' has no field '{}'", item.get_title(), field));
+    }
+    item.set_field(field, value.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::serialize_data;
+    use crate::data::tests::{note, showcase};
+    use serde_json::json;
+
+    #[test]
+    fn all_operations_apply_in_order() {
+        let mut data = showcase(&[note("a", "1"), note("b", "2")]);
+        let ops = [
+            Op::Move { from: 2, to: 1 },
+            Op::Set { index: 2, field: "content".to_string(), value: json!("changed") },
+        ];
+        apply_ops(&mut data, &ops).unwrap();
+        let saved: Value = serde_json::from_str(&serialize_data(&data).unwrap()).unwrap();
+        assert_eq!(saved["showcase"][0]["Note"]["title"], "b");
+        assert_eq!(saved["showcase"][1]["Note"]["content"], "changed");
+    }
+
+    #[test]
+    fn a_failing_operation_leaves_the_document_unchanged() {
+        let mut data = showcase(&[note("a", "1"), note("b", "2")]);
+        let before = serialize_data(&data).unwrap();
+        let ops = [
+            Op::Move { from: 2, to: 1 },
+            Op::Set { index: 1, field: "title".to_string(), value: json!("changed") },
+            Op::Reshape { index: 9, shape: "2x4".to_string() },
+        ];
+        let error = apply_ops(&mut data, &ops).unwrap_err();
+        assert_eq!(error.to_string(), "Operation #3 failed");
+        assert_eq!(serialize_data(&data).unwrap(), before);
+    }
+}
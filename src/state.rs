@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::xdg_home;
+
+const THEMES_FILE: &str = "themes.toml";
+
+/// The theme last selected for `content_file`, if any.
+pub fn remembered_theme(content_file: &str) -> Option<String> {
+    let themes = load_themes().ok()?;
+    themes
+        .get(&state_key(content_file))
+        .and_then(toml::Value::as_str)
+        .map(|s| s.to_string())
+}
+
+/// Remember `theme` as the selected theme for `content_file`.
+pub fn remember_theme(content_file: &str, theme: &str) -> Result<()> {
+    let dir = state_home().context("No state directory found")?.join("obentou");
+    let mut themes = load_themes().unwrap_or_default();
+    themes.insert(state_key(content_file), toml::Value::String(theme.to_string()));
+
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(THEMES_FILE), toml::to_string(&themes)?)?;
+    Ok(())
+}
+
+fn load_themes() -> Result<toml::Table> {
+    let path = state_home().context("No state directory found")?.join("obentou").join(THEMES_FILE);
+    let content = fs::read_to_string(path)?;
+    Ok(toml::from_str(&content)?)
+}
+
+// Content files are keyed by absolute path, whether they exist yet or not
+fn state_key(content_file: &str) -> String {
+    let path = Path::new(content_file);
+    let absolute = path
+        .canonicalize()
+        .or_else(|_| env::current_dir().map(|cwd| cwd.join(path)))
+        .unwrap_or_else(|_| path.to_path_buf());
+    absolute.to_string_lossy().to_string()
+}
+
+fn state_home() -> Option<PathBuf> {
+    xdg_home("XDG_STATE_HOME", ".local/state")
+}
@@ -0,0 +1,224 @@
+use anyhow::{anyhow, Context, Result};
+
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+/// Number of previous versions kept next to a content file.
+pub const BACKUP_COUNT: usize = 5;
+
+/// What a file looked like when it was read or written, to tell whether
+/// someone else has changed it since.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl Fingerprint {
+
+    /// Fingerprint of the file as it is now, `None` if it does not exist.
+    pub fn of_file(filename: &str) -> Option<Fingerprint> {
+        let path = resolve(filename);
+        let contents = fs::read(&path).ok()?;
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        Some(Fingerprint {
+            modified,
+            len: contents.len() as u64,
+            hash: hash_bytes(&contents),
+        })
+    }
+
+    /// Whether the file still has the contents this fingerprint was taken from.
+    /// A touched file with the same contents is not a change.
+    pub fn matches(&self, filename: &str) -> bool {
+        let path = resolve(filename);
+        match fs::metadata(&path) {
+            Ok(m) if m.len() == self.len && m.modified().ok() == self.modified => true,
+            Ok(m) if m.len() == self.len => fs::read(&path).is_ok_and(|c| hash_bytes(&c) == self.hash),
+            _ => false,
+        }
+    }
+
+}
+
+/// Whether `filename` differs from `known`, `None` meaning it did not exist.
+pub fn changed_on_disk(filename: &str, known: Option<&Fingerprint>) -> bool {
+    match known {
+        Some(fingerprint) => !fingerprint.matches(filename),
+        None => resolve(filename).exists(),
+    }
+}
+
+/// Replace `filename` with `contents` without ever leaving it half written.
+///
+/// The contents go to a temp file in the same directory, which is synced and
+/// renamed over the target. The version being replaced is kept as
+/// `<file>.1.bak`, older ones shift up to `<file>.<BACKUP_COUNT>.bak`.
+pub fn write_atomic(filename: &str, contents: &[u8]) -> Result<()> {
+    let path = resolve(filename);
+
+    let previous = fs::read(&path).ok();
+    if previous.as_deref() == Some(contents) {
+        return Ok(());
+    }
+
+    let tmp_path = temp_path(&path);
+    if let Err(e) = write_temp(&tmp_path, &path, contents) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.context(format!("Failed to write {}", filename)));
+    }
+
+    if previous.is_some() {
+        if let Err(e) = rotate_backups(&path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.context(format!("Failed to back up {}", filename)));
+        }
+    }
+
+    fs::rename(&tmp_path, &path).with_context(|| format!("Failed to replace {}", filename))?;
+    sync_dir(&path);
+    Ok(())
+}
+
+/// Existing backups of `filename`, newest first, with their number.
+pub fn list_backups(filename: &str) -> Vec<(usize, PathBuf)> {
+    let path = resolve(filename);
+    (1..=BACKUP_COUNT)
+        .map(|n| (n, backup_path(&path, n)))
+        .filter(|(_, backup)| backup.exists())
+        .collect()
+}
+
+/// Put backup number `n` back in place. The current version becomes a backup
+/// itself, so a restore can be undone with another one.
+pub fn restore_backup(filename: &str, n: usize) -> Result<PathBuf> {
+    if n == 0 || n > BACKUP_COUNT {
+        return Err(anyhow!("Backup number must be between 1 and {}", BACKUP_COUNT));
+    }
+    let backup = backup_path(&resolve(filename), n);
+    let contents = fs::read(&backup).with_context(|| format!("No backup {} of {}", n, filename))?;
+    write_atomic(filename, &contents)?;
+    Ok(backup)
+}
+
+fn hash_bytes(contents: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn write_temp(tmp_path: &Path, path: &Path, contents: &[u8]) -> Result<()> {
+    let mut file = File::create(tmp_path)?;
+    file.write_all(contents)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+// <file>.1.bak is the newest backup; the oldest one falls off the end
+fn rotate_backups(path: &Path) -> Result<()> {
+    for n in (1..BACKUP_COUNT).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, n + 1))?;
+        }
+    }
+    // Copy rather than rename, so the target exists until the final rename
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+// Write through symlinks instead of replacing them with a regular file
+fn resolve(filename: &str) -> PathBuf {
+    let path = Path::new(filename);
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+        }
+        _ => path.to_path_buf(),
+    }
+}
+
+fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.bak", n));
+    path.with_file_name(name)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.tmp", process::id()));
+    path.with_file_name(name)
+}
+
+// Make the rename itself durable; not every platform can open a directory
+fn sync_dir(path: &Path) {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory per test, so tests can run in parallel
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("obentou-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn saves_rotate_backups_newest_first() {
+        let dir = scratch("rotate");
+        let file = dir.join("content.json");
+        let filename = file.to_str().unwrap();
+        for version in 0..=BACKUP_COUNT + 2 {
+            write_atomic(filename, format!("v{}", version).as_bytes()).unwrap();
+        }
+        // Writing the same contents again is not a new version
+        write_atomic(filename, format!("v{}", BACKUP_COUNT + 2).as_bytes()).unwrap();
+
+        assert_eq!(fs::read_to_string(&file).unwrap(), "v7");
+        let backups = list_backups(filename);
+        assert_eq!(backups.iter().map(|(n, _)| *n).collect::<Vec<_>>(), (1..=BACKUP_COUNT).collect::<Vec<_>>());
+        for (n, backup) in backups {
+            assert_eq!(fs::read_to_string(backup).unwrap(), format!("v{}", BACKUP_COUNT + 2 - n));
+        }
+        assert!(!backup_path(&file, BACKUP_COUNT + 1).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_restore_can_be_undone() {
+        let dir = scratch("restore");
+        let file = dir.join("content.json");
+        let filename = file.to_str().unwrap();
+        for version in ["v1", "v2", "v3"] {
+            write_atomic(filename, version.as_bytes()).unwrap();
+        }
+
+        restore_backup(filename, 2).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "v1");
+        assert_eq!(fs::read_to_string(backup_path(&file, 1)).unwrap(), "v3");
+
+        // The version replaced by the restore is now backup 1
+        restore_backup(filename, 1).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "v3");
+        assert!(restore_backup(filename, BACKUP_COUNT + 1).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use crate::data::Data;
+
+use serde_json::{json, Value};
+
+/// A problem found in a content file, located by card and JSON pointer.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub index: usize,
+    pub card_type: String,
+    pub field: Option<String>,
+    pub path: String,
+    pub message: String,
+}
+
+/// Check the profile and every showcase card, collecting all problems.
+pub fn validate_data(data: &Data) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    for (i, item) in data.items.iter().enumerate() {
+        let card_path = if i == 0 {
+            "/profile".to_string()
+        } else {
+            format!("/showcase/{}/{}", i - 1, escape_pointer(item.get_title()))
+        };
+
+        let json_str = item.get_lines().join("\n");
+        let problems = data.metadata.find_problems(&json_str, item.get_title());
+
+        for problem in problems {
+            let path = match &problem.field {
+                Some(field) => format!("{}/{}", card_path, escape_pointer(field)),
+                None => card_path.clone(),
+            };
+            diagnostics.push(Diagnostic {
+                index: i,
+                card_type: item.get_title().to_string(),
+                field: problem.field,
+                path,
+                message: problem.message,
+            });
+        }
+    }
+
+    diagnostics
+}
+
+impl Diagnostic {
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "index": self.index,
+            "card_type": self.card_type,
+            "field": self.field,
+            "path": self.path,
+            "message": self.message,
+        })
+    }
+
+}
+
+fn escape_pointer(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}